@@ -0,0 +1,726 @@
+use crate::{
+    geo::Coord,
+    osm::{OsmId, OsmWay},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    str::FromStr,
+};
+
+/// Ways of getting the surveyor's attention when an alert triggers.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum AlertNotification {
+    Vibration,
+    Sound,
+    Visual,
+    System,
+}
+
+impl AlertNotification {
+    pub const ALL: [AlertNotification; 4] = [
+        AlertNotification::Vibration,
+        AlertNotification::Sound,
+        AlertNotification::Visual,
+        AlertNotification::System,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AlertNotification::Vibration => "Vibrate",
+            AlertNotification::Sound => "Play a sound",
+            AlertNotification::Visual => "Show a message",
+            AlertNotification::System => "Show a system notification, also when Surway is hidden",
+        }
+    }
+}
+
+/// A named rule as written by the user, like `highway in [primary, secondary] and missing name`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct AlertRule {
+    pub name: String,
+    pub rule: String,
+    pub enabled: bool,
+}
+
+impl AlertRule {
+    fn new(name: &str, rule: &str) -> AlertRule {
+        AlertRule {
+            name: name.into(),
+            rule: rule.into(),
+            enabled: false,
+        }
+    }
+
+    pub fn parse(&self) -> Result<Rule, String> {
+        self.rule.parse()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct AlertSettings {
+    pub rules: Vec<AlertRule>,
+    pub notifications: Vec<AlertNotification>,
+    /// Minutes before a rule may alert again about the same way.
+    #[serde(default = "default_repeat_after")]
+    pub repeat_after: f64,
+}
+
+fn default_repeat_after() -> f64 {
+    10.0
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        AlertSettings {
+            rules: vec![
+                AlertRule::new(
+                    "Major road without a name",
+                    "highway in [motorway, trunk, primary, secondary, tertiary] and missing name",
+                ),
+                AlertRule::new("No longer near a way", "way_distance > 25 m for 10 s"),
+            ],
+            notifications: vec![AlertNotification::Vibration, AlertNotification::Visual],
+            repeat_after: default_repeat_after(),
+        }
+    }
+}
+
+impl AlertSettings {
+    /// Adds a new rule, which is enabled right away.
+    pub fn add(&mut self, name: &str, rule: &str) -> Result<(), String> {
+        let name = name.trim();
+
+        if name.is_empty() {
+            return Err("The rule needs a name".into());
+        }
+
+        if self.rules.iter().any(|r| r.name == name) {
+            return Err(format!("There is already a rule named {}", name));
+        }
+
+        rule.parse::<Rule>()?;
+
+        self.rules.push(AlertRule {
+            enabled: true,
+            ..AlertRule::new(name, rule.trim())
+        });
+
+        Ok(())
+    }
+
+    pub fn notifies_by(&self, notification: AlertNotification) -> bool {
+        self.notifications.contains(&notification)
+    }
+}
+
+/// What the rules are checked against: the way nearest to the surveyor, and how far away it is.
+/// Distances to the ends of the way are unknown when their nodes aren't loaded.
+pub struct AlertContext<'a> {
+    pub position: Coord,
+    pub way: &'a OsmWay,
+    pub way_distance: f64,
+    pub start_distance: Option<f64>,
+    pub end_distance: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    pub way_id: OsmId,
+    pub message: String,
+    pub time: f64,
+    pub position: Coord,
+}
+
+/// Keeps track of which rules hold, and for how long, to know when to alert.
+#[derive(Debug, Default)]
+pub struct AlertMonitor {
+    states: HashMap<String, RuleState>,
+    /// When each rule last alerted about each way, in milliseconds.
+    last_alerts: HashMap<(String, OsmId), f64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RuleState {
+    /// When the rule started to hold, in milliseconds.
+    since: f64,
+    /// The way the rule holds for, if it depends on the tags of the way.
+    way_id: Option<OsmId>,
+    alerted: bool,
+}
+
+impl AlertMonitor {
+    /// Checks the enabled rules at the time `now` (in milliseconds), and returns alerts for the
+    /// rules that have held long enough. A rule alerts once, and then again only after it has
+    /// stopped holding, or when it holds for another way. Either way, it doesn't alert about the
+    /// same way more often than the settings allow, so passing back and forth between two ways
+    /// doesn't alert about both every time.
+    pub fn check(
+        &mut self,
+        settings: &AlertSettings,
+        context: &AlertContext,
+        now: f64,
+    ) -> Vec<Alert> {
+        let repeat_after = settings.repeat_after * 60.0 * 1000.0;
+        let mut states = HashMap::new();
+        let mut alerts = vec![];
+
+        self.last_alerts
+            .retain(|_, &mut time| now - time < repeat_after);
+
+        for alert_rule in settings.rules.iter().filter(|r| r.enabled) {
+            let rule = match alert_rule.parse() {
+                Ok(rule) => rule,
+                Err(_) => continue,
+            };
+
+            if !rule.condition.matches(context) {
+                continue;
+            }
+
+            let way_id = rule.condition.uses_tags().then_some(context.way.id);
+
+            let mut state = match self.states.get(&alert_rule.name) {
+                Some(&state) if state.way_id == way_id => state,
+                _ => RuleState {
+                    since: now,
+                    way_id,
+                    alerted: false,
+                },
+            };
+
+            if !state.alerted && now - state.since >= rule.duration * 1000.0 {
+                state.alerted = true;
+                let key = (alert_rule.name.clone(), context.way.id);
+
+                if let Entry::Vacant(entry) = self.last_alerts.entry(key) {
+                    entry.insert(now);
+
+                    let message = match way_id {
+                        Some(_) => format!("{}: {}", alert_rule.name, context.way.label()),
+                        None => alert_rule.name.clone(),
+                    };
+
+                    alerts.push(Alert {
+                        rule: alert_rule.name.clone(),
+                        way_id: context.way.id,
+                        message,
+                        time: now,
+                        position: context.position,
+                    });
+                }
+            }
+
+            states.insert(alert_rule.name.clone(), state);
+        }
+
+        self.states = states;
+        alerts
+    }
+}
+
+/// A parsed alert rule: a condition, and how many seconds it must hold before alerting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    condition: Condition,
+    duration: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Has(String),
+    Missing(String),
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, Vec<String>),
+    Distance(Distance, Comparison, f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Distance {
+    Way,
+    Start,
+    End,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    fn matches(&self, context: &AlertContext) -> bool {
+        let tag = |key: &str| context.way.tag(key);
+
+        match self {
+            Condition::And(a, b) => a.matches(context) && b.matches(context),
+            Condition::Or(a, b) => a.matches(context) || b.matches(context),
+            Condition::Not(condition) => !condition.matches(context),
+            Condition::Has(key) => tag(key).is_some(),
+            Condition::Missing(key) => tag(key).is_none(),
+            Condition::Equals(key, value) => tag(key) == Some(value),
+            Condition::NotEquals(key, value) => tag(key) != Some(value),
+            Condition::In(key, values) => tag(key).map_or(false, |v| values.iter().any(|x| x == v)),
+            Condition::Distance(distance, comparison, limit) => {
+                // An end that isn't loaded counts as far away
+                let actual = match distance {
+                    Distance::Way => context.way_distance,
+                    Distance::Start => context.start_distance.unwrap_or(f64::INFINITY),
+                    Distance::End => context.end_distance.unwrap_or(f64::INFINITY),
+                };
+
+                match comparison {
+                    Comparison::Less => actual < *limit,
+                    Comparison::LessOrEqual => actual <= *limit,
+                    Comparison::Greater => actual > *limit,
+                    Comparison::GreaterOrEqual => actual >= *limit,
+                }
+            }
+        }
+    }
+
+    /// Whether the condition looks at the tags of the way, rather than only at distances.
+    fn uses_tags(&self) -> bool {
+        match self {
+            Condition::And(a, b) | Condition::Or(a, b) => a.uses_tags() || b.uses_tags(),
+            Condition::Not(condition) => condition.uses_tags(),
+            Condition::Distance(..) => false,
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    /// Reads rules like `highway in [primary, secondary] and missing name` or
+    /// `way_distance > 25 m for 10 s`. Conditions are combined with `and`, `or` and `not`, where
+    /// `and` binds tighter than `or`.
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(rule)?,
+            position: 0,
+        };
+
+        let condition = parser.or()?;
+
+        let duration = if parser.keyword("for") {
+            parser.quantity(&[("s", 1.0), ("sec", 1.0), ("min", 60.0)])?
+        } else {
+            0.0
+        };
+
+        match parser.next() {
+            None => Ok(Rule {
+                condition,
+                duration,
+            }),
+            Some(token) => Err(format!("Unexpected {} in rule", token)),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 9] = ["!=", ">=", "<=", "=", ">", "<", "[", "]", ","];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+/// Splits a rule into words and symbols. Values with spaces in them can be quoted.
+fn tokenize(rule: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = rule.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').ok_or("Missing end quote in rule")?;
+            tokens.push(Token::Word(quoted[..end].into()));
+            rest = &quoted[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "!=<>[],\"".contains(c))
+                .unwrap_or(rest.len());
+
+            if end == 0 {
+                return Err(format!("Unexpected {} in rule", &rest[..1]));
+            }
+
+            tokens.push(Token::Word(rest[..end].into()));
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Moves past the next word if it is the given keyword.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word == keyword);
+
+        if found {
+            self.position += 1;
+        }
+
+        found
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => Err(format!("Expected a word, got {}", token)),
+            None => Err("The rule ends too early".into()),
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+            Some(token) => Err(format!("Expected {}, got {}", symbol, token)),
+            None => Err(format!("Expected {} at the end of the rule", symbol)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+
+        while self.keyword("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.condition()?;
+
+        while self.keyword("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.condition()?));
+        }
+
+        Ok(condition)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        if self.keyword("not") {
+            return Ok(Condition::Not(Box::new(self.condition()?)));
+        }
+
+        if self.keyword("missing") {
+            return Ok(Condition::Missing(self.word()?));
+        }
+
+        if self.keyword("has") {
+            return Ok(Condition::Has(self.word()?));
+        }
+
+        let key = self.word()?;
+
+        if self.keyword("in") {
+            return Ok(Condition::In(key, self.list()?));
+        }
+
+        match self.next() {
+            Some(Token::Symbol("=")) => Ok(Condition::Equals(key, self.word()?)),
+            Some(Token::Symbol("!=")) => Ok(Condition::NotEquals(key, self.word()?)),
+            Some(Token::Symbol(symbol @ (">" | ">=" | "<" | "<="))) => {
+                let distance = match key.as_str() {
+                    "way_distance" => Distance::Way,
+                    "start_distance" => Distance::Start,
+                    "end_distance" => Distance::End,
+                    _ => return Err(format!("{} is not a distance", key)),
+                };
+
+                let comparison = match symbol {
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    "<" => Comparison::Less,
+                    _ => Comparison::LessOrEqual,
+                };
+
+                let meters = self.quantity(&[("m", 1.0), ("km", 1000.0)])?;
+                Ok(Condition::Distance(distance, comparison, meters))
+            }
+            Some(token) => Err(format!("Unexpected {} after {}", token, key)),
+            None => Err(format!("Missing condition for {}", key)),
+        }
+    }
+
+    /// A list of values, like `[primary, secondary]`.
+    fn list(&mut self) -> Result<Vec<String>, String> {
+        self.symbol("[")?;
+        let mut values = vec![self.word()?];
+
+        while matches!(self.peek(), Some(Token::Symbol(","))) {
+            self.position += 1;
+            values.push(self.word()?);
+        }
+
+        self.symbol("]")?;
+        Ok(values)
+    }
+
+    /// A number with one of the given units, written together (`25m`) or apart (`25 m`). The
+    /// first unit is assumed if none is given.
+    fn quantity(&mut self, units: &[(&str, f64)]) -> Result<f64, String> {
+        let word = self.word()?;
+        let split = word
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(word.len());
+        let (number, mut unit) = (&word[..split], word[split..].to_string());
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("Expected a number, got {}", word))?;
+
+        if unit.is_empty() {
+            if let Some(Token::Word(next)) = self.peek() {
+                if units.iter().any(|(u, _)| u == next) {
+                    unit = next.clone();
+                    self.position += 1;
+                }
+            }
+        }
+
+        if unit.is_empty() {
+            return Ok(number * units[0].1);
+        }
+
+        units
+            .iter()
+            .find(|(u, _)| *u == unit)
+            .map(|(_, factor)| number * factor)
+            .ok_or_else(|| format!("Unknown unit {}", unit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm::OsmTag;
+
+    fn way(id: OsmId, tags: &[(&str, &str)]) -> OsmWay {
+        OsmWay {
+            id,
+            version: None,
+            nds: vec![],
+            tags: tags
+                .iter()
+                .map(|(k, v)| OsmTag {
+                    k: k.to_string(),
+                    v: v.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn context(way: &OsmWay, way_distance: f64) -> AlertContext {
+        AlertContext {
+            position: Coord {
+                lat: 60.39,
+                lon: 5.32,
+            },
+            way,
+            way_distance,
+            start_distance: None,
+            end_distance: Some(40.0),
+        }
+    }
+
+    fn matches(rule: &str, context: &AlertContext) -> bool {
+        rule.parse::<Rule>()
+            .expect("Invalid rule")
+            .condition
+            .matches(context)
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(
+            "highway in [primary, \"living street\"] and missing name".parse(),
+            Ok(Rule {
+                condition: Condition::And(
+                    Box::new(Condition::In(
+                        "highway".into(),
+                        vec!["primary".into(), "living street".into()]
+                    )),
+                    Box::new(Condition::Missing("name".into()))
+                ),
+                duration: 0.0
+            })
+        );
+        assert_eq!(
+            "way_distance > 25 m for 10 s".parse(),
+            Ok(Rule {
+                condition: Condition::Distance(Distance::Way, Comparison::Greater, 25.0),
+                duration: 10.0
+            })
+        );
+        assert_eq!(
+            "end_distance<=0.1km for 2min"
+                .parse::<Rule>()
+                .map(|r| r.duration),
+            Ok(120.0)
+        );
+
+        assert!("".parse::<Rule>().is_err());
+        assert!("highway in [primary".parse::<Rule>().is_err());
+        assert!("name > 25 m".parse::<Rule>().is_err());
+        assert!("way_distance > 25 miles".parse::<Rule>().is_err());
+        assert!("missing name for".parse::<Rule>().is_err());
+        assert!("missing name surface".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_match_rule() {
+        let road = way(1, &[("highway", "primary"), ("ref", "E39")]);
+        let near = context(&road, 5.0);
+        let far = context(&road, 30.0);
+
+        assert!(matches(
+            "highway in [primary, secondary] and missing name",
+            &near
+        ));
+        assert!(!matches("highway = primary and has name", &near));
+        assert!(matches("has name or ref = E39", &near));
+        assert!(matches("not surface = gravel", &near));
+        assert!(matches("surface != gravel", &near));
+        assert!(!matches("way_distance > 25 m", &near));
+        assert!(matches("way_distance > 25 m", &far));
+        assert!(matches("start_distance > 1 km", &near));
+        assert!(matches("end_distance < 50 m", &near));
+    }
+
+    #[test]
+    fn test_alert_after_duration() {
+        let mut settings = AlertSettings::default();
+        settings.rules[1].enabled = true;
+
+        let road = way(1, &[("highway", "primary")]);
+        let other_road = way(2, &[("highway", "primary")]);
+        let mut monitor = AlertMonitor::default();
+
+        assert!(monitor
+            .check(&settings, &context(&road, 30.0), 0.0)
+            .is_empty());
+        assert!(monitor
+            .check(&settings, &context(&other_road, 30.0), 5000.0)
+            .is_empty());
+
+        let alerts = monitor.check(&settings, &context(&other_road, 40.0), 10000.0);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].message, "No longer near a way");
+
+        // Only once, until we have been back near a way
+        assert!(monitor
+            .check(&settings, &context(&road, 40.0), 20000.0)
+            .is_empty());
+        monitor.check(&settings, &context(&road, 10.0), 21000.0);
+        monitor.check(&settings, &context(&road, 30.0), 22000.0);
+        assert_eq!(
+            monitor
+                .check(&settings, &context(&road, 30.0), 32000.0)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_alert_for_each_way() {
+        let mut settings = AlertSettings::default();
+        settings.rules[0].enabled = true;
+
+        let road = way(1, &[("highway", "primary"), ("ref", "E39")]);
+        let other_road = way(2, &[("highway", "secondary")]);
+        let mut monitor = AlertMonitor::default();
+
+        let alerts = monitor.check(&settings, &context(&road, 5.0), 0.0);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].message, "Major road without a name: E39");
+
+        assert!(monitor
+            .check(&settings, &context(&road, 5.0), 1000.0)
+            .is_empty());
+        assert_eq!(
+            monitor
+                .check(&settings, &context(&other_road, 5.0), 2000.0)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_repeat_after() {
+        let mut settings = AlertSettings::default();
+        settings.rules[0].enabled = true;
+
+        let road = way(1, &[("highway", "primary")]);
+        let other_road = way(2, &[("highway", "primary")]);
+        let mut monitor = AlertMonitor::default();
+        let mut count = |way, minutes: f64| {
+            monitor
+                .check(&settings, &context(way, 5.0), minutes * 60.0 * 1000.0)
+                .len()
+        };
+
+        // Passing back and forth between the two roads at a crossing
+        assert_eq!(count(&road, 0.0), 1);
+        assert_eq!(count(&other_road, 1.0), 1);
+        assert_eq!(count(&road, 2.0), 0);
+        assert_eq!(count(&other_road, 3.0), 0);
+        assert_eq!(count(&road, 12.0), 1);
+    }
+
+    #[test]
+    fn test_add_rule() {
+        let mut settings = AlertSettings::default();
+
+        assert!(settings
+            .add(" Unpaved ", "surface in [gravel, dirt]")
+            .is_ok());
+        assert!(settings.rules[2].enabled);
+        assert_eq!(settings.rules[2].name, "Unpaved");
+
+        assert!(settings.add("Unpaved", "has surface").is_err());
+        assert!(settings.add("", "has surface").is_err());
+        assert!(settings.add("Broken", "surface in gravel").is_err());
+    }
+}
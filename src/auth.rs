@@ -0,0 +1,101 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use urlencoding::encode;
+
+const AUTHORIZE_URL: &str = "https://www.openstreetmap.org/oauth2/authorize";
+const SCOPES: &str = "read_prefs write_api write_notes write_gpx";
+
+/// A login that has been started, but not completed. Kept in session storage while the user is
+/// away logging in to OSM.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingLogin {
+    pub state: String,
+    pub code_verifier: String,
+}
+
+impl PendingLogin {
+    pub fn new() -> PendingLogin {
+        PendingLogin {
+            state: random_string(32),
+            code_verifier: random_string(64),
+        }
+    }
+
+    pub fn authorize_url(&self, client_id: &str, redirect_uri: &str) -> String {
+        format!(
+            "{}?response_type=code&client_id={}&scope={}&redirect_uri={}&state={}&code_challenge={}&code_challenge_method=S256",
+            AUTHORIZE_URL,
+            encode(client_id),
+            encode(SCOPES),
+            encode(redirect_uri),
+            encode(&self.state),
+            code_challenge(&self.code_verifier)
+        )
+    }
+
+    /// The form body exchanging the authorization code for an access token.
+    pub fn token_request_body(&self, code: &str, client_id: &str, redirect_uri: &str) -> String {
+        format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+            encode(code),
+            encode(redirect_uri),
+            encode(client_id),
+            encode(&self.code_verifier)
+        )
+    }
+}
+
+/// The S256 code challenge, as described in RFC 7636.
+pub fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+fn random_string(length: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_challenge() {
+        // From appendix B of RFC 7636
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_pending_login() {
+        let login = PendingLogin::new();
+        let other = PendingLogin::new();
+
+        assert_eq!(login.code_verifier.len(), 64);
+        assert_ne!(login.state, other.state);
+
+        let url = login.authorize_url("client", "http://127.0.0.1:8088/callback");
+        assert!(
+            url.starts_with("https://www.openstreetmap.org/oauth2/authorize?response_type=code")
+        );
+        assert!(url.contains("&scope=read_prefs%20write_api%20write_notes%20write_gpx&"));
+        assert!(url.contains("&redirect_uri=http%3A%2F%2F127.0.0.1%3A8088%2Fcallback&"));
+        assert!(url.contains(&format!("&state={}&", login.state)));
+        assert!(url.contains(&format!(
+            "&code_challenge={}&code_challenge_method=S256",
+            code_challenge(&login.code_verifier)
+        )));
+
+        let body = login.token_request_body("abc", "client", "http://127.0.0.1:8088/callback");
+        assert!(body.ends_with(&format!("&code_verifier={}", login.code_verifier)));
+    }
+}
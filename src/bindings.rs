@@ -11,22 +11,25 @@ extern "C" {
     pub fn longitude(this: &GeolocationCoordinates) -> f64;
 
     #[wasm_bindgen(method, getter)]
-    pub fn altitude(this: &GeolocationCoordinates) -> f64;
+    pub fn altitude(this: &GeolocationCoordinates) -> Option<f64>;
 
     #[wasm_bindgen(method, getter)]
     pub fn accuracy(this: &GeolocationCoordinates) -> f64;
 
     #[wasm_bindgen(method, getter, js_name = "altitudeAccuracy")]
-    pub fn altitude_accuracy(this: &GeolocationCoordinates) -> f64;
+    pub fn altitude_accuracy(this: &GeolocationCoordinates) -> Option<f64>;
 
     #[wasm_bindgen(method, getter)]
-    pub fn heading(this: &GeolocationCoordinates) -> f64;
+    pub fn heading(this: &GeolocationCoordinates) -> Option<f64>;
 
     #[wasm_bindgen(method, getter)]
-    pub fn speed(this: &GeolocationCoordinates) -> f64;
+    pub fn speed(this: &GeolocationCoordinates) -> Option<f64>;
 
     pub type GeolocationPosition;
 
     #[wasm_bindgen(method, getter)]
     pub fn coords(this: &GeolocationPosition) -> GeolocationCoordinates;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn timestamp(this: &GeolocationPosition) -> f64;
 }
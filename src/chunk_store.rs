@@ -0,0 +1,190 @@
+use crate::geo::BoundingBox;
+use js_sys::{Array, Promise};
+use seed::{prelude::*, window, JsFuture};
+use serde::{Deserialize, Serialize};
+use web_sys::{
+    IdbDatabase, IdbObjectStore, IdbObjectStoreParameters, IdbRequest, IdbTransactionMode,
+};
+
+const DATABASE_NAME: &str = "surway";
+const DATABASE_VERSION: u32 = 1;
+const CHUNK_STORE_NAME: &str = "chunks";
+
+/// A downloaded OSM chunk, as stored in IndexedDB.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredChunk {
+    pub key: String,
+    pub bbox: BoundingBox,
+    /// The Overpass query the chunk was downloaded with.
+    #[serde(default)]
+    pub query: String,
+    pub time: f64,
+    pub data: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ChunkStoreSettings {
+    pub quota_bytes: usize,
+    pub expiry_days: f64,
+}
+
+impl Default for ChunkStoreSettings {
+    fn default() -> Self {
+        ChunkStoreSettings {
+            quota_bytes: 25 * 1024 * 1024,
+            expiry_days: 14.0,
+        }
+    }
+}
+
+impl ChunkStoreSettings {
+    fn is_expired(&self, chunk: &StoredChunk, now: f64) -> bool {
+        now - chunk.time > self.expiry_days * 24.0 * 60.0 * 60.0 * 1000.0
+    }
+}
+
+impl StoredChunk {
+    pub fn new(bbox: BoundingBox, query: String, time: f64, data: String) -> StoredChunk {
+        StoredChunk {
+            key: format!(
+                "{:.6},{:.6},{:.6},{:.6},{}",
+                bbox.lower_left.lon,
+                bbox.lower_left.lat,
+                bbox.upper_right.lon,
+                bbox.upper_right.lat,
+                query
+            ),
+            bbox,
+            query,
+            time,
+            data,
+        }
+    }
+}
+
+/// Stores a chunk, then removes expired chunks and the oldest ones exceeding the quota.
+pub async fn save(
+    chunk: StoredChunk,
+    settings: ChunkStoreSettings,
+    now: f64,
+) -> Result<(), JsValue> {
+    let db = open().await?;
+
+    let value = JsValue::from_serde(&chunk).map_err(|e| JsValue::from(e.to_string()))?;
+    complete(&store(&db, IdbTransactionMode::Readwrite)?.put(&value)?).await?;
+
+    let mut chunks = all_chunks(&db).await?;
+    chunks.sort_by(|a, b| {
+        b.time
+            .partial_cmp(&a.time)
+            .expect("Could not compare times")
+    });
+
+    let mut total_size = 0;
+
+    for chunk in chunks {
+        total_size += chunk.data.len();
+
+        if settings.is_expired(&chunk, now) || total_size > settings.quota_bytes {
+            complete(&store(&db, IdbTransactionMode::Readwrite)?.delete(&chunk.key.into())?)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads all chunks downloaded with the given query that haven't expired and overlap the given
+/// bounding box, oldest first.
+pub async fn load(
+    bbox: BoundingBox,
+    query: String,
+    settings: ChunkStoreSettings,
+    now: f64,
+) -> Result<Vec<StoredChunk>, JsValue> {
+    let db = open().await?;
+    let mut chunks: Vec<StoredChunk> = all_chunks(&db)
+        .await?
+        .into_iter()
+        .filter(|chunk| {
+            chunk.query == query && !settings.is_expired(chunk, now) && chunk.bbox.intersects(&bbox)
+        })
+        .collect();
+
+    chunks.sort_by(|a, b| {
+        a.time
+            .partial_cmp(&b.time)
+            .expect("Could not compare times")
+    });
+    Ok(chunks)
+}
+
+/// Returns the number of stored chunks and their total size in bytes.
+pub async fn usage() -> Result<(usize, usize), JsValue> {
+    let db = open().await?;
+    let chunks = all_chunks(&db).await?;
+    Ok((
+        chunks.len(),
+        chunks.iter().map(|chunk| chunk.data.len()).sum(),
+    ))
+}
+
+pub async fn clear() -> Result<(), JsValue> {
+    let db = open().await?;
+    complete(&store(&db, IdbTransactionMode::Readwrite)?.clear()?).await?;
+    Ok(())
+}
+
+async fn open() -> Result<IdbDatabase, JsValue> {
+    let factory = window()
+        .indexed_db()?
+        .ok_or_else(|| JsValue::from("IndexedDB is not available"))?;
+
+    let request = factory.open_with_u32(DATABASE_NAME, DATABASE_VERSION)?;
+    let upgrade_request = request.clone();
+
+    let on_upgrade_needed = Closure::wrap(Box::new(move || {
+        let db: IdbDatabase = upgrade_request
+            .result()
+            .expect("Unable to get database being upgraded")
+            .unchecked_into();
+
+        if !db.object_store_names().contains(CHUNK_STORE_NAME) {
+            db.create_object_store_with_optional_parameters(
+                CHUNK_STORE_NAME,
+                IdbObjectStoreParameters::new().key_path(Some(&"key".into())),
+            )
+            .expect("Unable to create chunk store");
+        }
+    }) as Box<dyn FnMut()>);
+
+    request.set_onupgradeneeded(Some(on_upgrade_needed.as_ref().unchecked_ref()));
+    let db = complete(&request).await?;
+
+    Ok(db.unchecked_into())
+}
+
+fn store(db: &IdbDatabase, mode: IdbTransactionMode) -> Result<IdbObjectStore, JsValue> {
+    db.transaction_with_str_and_mode(CHUNK_STORE_NAME, mode)?
+        .object_store(CHUNK_STORE_NAME)
+}
+
+async fn all_chunks(db: &IdbDatabase) -> Result<Vec<StoredChunk>, JsValue> {
+    let result = complete(&store(db, IdbTransactionMode::Readonly)?.get_all()?).await?;
+
+    Ok(Array::from(&result)
+        .iter()
+        .filter_map(|value| value.into_serde().ok())
+        .collect())
+}
+
+/// Waits for an IndexedDB request to finish, and returns its result.
+async fn complete(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+
+    JsFuture::from(promise).await?;
+    request.result()
+}
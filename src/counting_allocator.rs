@@ -0,0 +1,58 @@
+//! An allocator counting the allocations made on each thread, so tests can compare the memory use
+//! of different ways of doing the same thing.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    static COUNT: Cell<usize> = const { Cell::new(0) };
+    static BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+fn record(size: usize) {
+    // The thread locals are gone while a thread is shutting down
+    let _ = COUNT.try_with(|count| count.set(count.get() + 1));
+    let _ = BYTES.try_with(|bytes| bytes.set(bytes.get() + size));
+}
+
+#[derive(Debug)]
+pub struct Allocations {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Runs the function, and counts the allocations it made.
+pub fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, Allocations) {
+    let (count, bytes) = (COUNT.with(Cell::get), BYTES.with(Cell::get));
+    let result = f();
+
+    let allocations = Allocations {
+        count: COUNT.with(Cell::get) - count,
+        bytes: BYTES.with(Cell::get) - bytes,
+    };
+
+    (result, allocations)
+}
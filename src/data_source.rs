@@ -0,0 +1,295 @@
+use crate::geo::BoundingBox;
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+
+pub const DEFAULT_OVERPASS_ENDPOINT: &str = "https://overpass-api.de/api/interpreter";
+pub const DEFAULT_OSM_API_ENDPOINT: &str = "https://api.openstreetmap.org/api/0.6";
+
+/// The format to download map data in. JSON is quicker to read than XML.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum DataFormat {
+    #[default]
+    Xml,
+    Json,
+}
+
+impl DataFormat {
+    pub const ALL: [DataFormat; 2] = [DataFormat::Xml, DataFormat::Json];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataFormat::Xml => "xml",
+            DataFormat::Json => "json",
+        }
+    }
+
+    pub fn parse(format: &str) -> Option<DataFormat> {
+        DataFormat::ALL.into_iter().find(|f| f.as_str() == format)
+    }
+}
+
+/// Somewhere to get OSM map data from.
+pub trait DataSource {
+    /// The URL to download the map data within the bounding box from, or `None` if the source
+    /// doesn't download anything.
+    fn map_url(&self, bbox: &BoundingBox, query: &str) -> Option<String>;
+
+    /// Whether the source only returns what the Overpass query asks for. Data from other sources
+    /// has to be filtered after downloading.
+    fn applies_query(&self) -> bool;
+
+    /// What the downloaded data depends on, besides the bounding box. Stored chunks are only
+    /// reused by sources giving the same value.
+    fn chunk_key(&self, query: &str) -> String {
+        if self.applies_query() {
+            query.into()
+        } else {
+            "map".into()
+        }
+    }
+}
+
+/// An Overpass API server, like the public one or a mirror of your own.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Overpass {
+    pub endpoint: String,
+    #[serde(default)]
+    pub format: DataFormat,
+}
+
+impl DataSource for Overpass {
+    fn map_url(&self, bbox: &BoundingBox, query: &str) -> Option<String> {
+        let query = match self.format {
+            DataFormat::Xml => query.to_string(),
+            DataFormat::Json => format!("[out:json]{}", query),
+        };
+
+        Some(format!(
+            "{}?data={}&bbox={}",
+            self.endpoint,
+            encode(&query),
+            bbox_param(bbox)
+        ))
+    }
+
+    fn applies_query(&self) -> bool {
+        true
+    }
+}
+
+/// The `map` call of the main OSM API, which returns everything within the bounding box. It is
+/// always up to date, but is limited to small areas.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct OsmApi {
+    pub endpoint: String,
+    #[serde(default)]
+    pub format: DataFormat,
+}
+
+impl DataSource for OsmApi {
+    fn map_url(&self, bbox: &BoundingBox, _query: &str) -> Option<String> {
+        let extension = match self.format {
+            DataFormat::Xml => "",
+            DataFormat::Json => ".json",
+        };
+
+        Some(format!(
+            "{}/map{}?bbox={}",
+            self.endpoint,
+            extension,
+            bbox_param(bbox)
+        ))
+    }
+
+    fn applies_query(&self) -> bool {
+        false
+    }
+}
+
+/// An OSM file loaded from disk. Nothing gets downloaded while using it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LocalFile {
+    pub name: Option<String>,
+}
+
+impl DataSource for LocalFile {
+    fn map_url(&self, _bbox: &BoundingBox, _query: &str) -> Option<String> {
+        None
+    }
+
+    fn applies_query(&self) -> bool {
+        false
+    }
+}
+
+/// The data source chosen in the settings.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum DataSourceSettings {
+    Overpass(Overpass),
+    OsmApi(OsmApi),
+    LocalFile(LocalFile),
+}
+
+impl Default for DataSourceSettings {
+    fn default() -> Self {
+        DataSourceSettings::Overpass(Overpass {
+            endpoint: DEFAULT_OVERPASS_ENDPOINT.into(),
+            format: DataFormat::default(),
+        })
+    }
+}
+
+impl DataSourceSettings {
+    pub const KINDS: [&'static str; 3] = ["overpass", "osm-api", "local-file"];
+
+    /// A fresh source of the given kind, with its default settings.
+    pub fn of_kind(kind: &str) -> Option<DataSourceSettings> {
+        match kind {
+            "overpass" => Some(DataSourceSettings::default()),
+            "osm-api" => Some(DataSourceSettings::OsmApi(OsmApi {
+                endpoint: DEFAULT_OSM_API_ENDPOINT.into(),
+                format: DataFormat::default(),
+            })),
+            "local-file" => Some(DataSourceSettings::LocalFile(LocalFile { name: None })),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DataSourceSettings::Overpass(_) => "overpass",
+            DataSourceSettings::OsmApi(_) => "osm-api",
+            DataSourceSettings::LocalFile(_) => "local-file",
+        }
+    }
+
+    pub fn describe_kind(kind: &str) -> &'static str {
+        match kind {
+            "overpass" => "Overpass API",
+            "osm-api" => "OSM API (everything in a small area)",
+            _ => "Local OSM file",
+        }
+    }
+
+    pub fn endpoint(&self) -> Option<&str> {
+        match self {
+            DataSourceSettings::Overpass(source) => Some(&source.endpoint),
+            DataSourceSettings::OsmApi(source) => Some(&source.endpoint),
+            DataSourceSettings::LocalFile(_) => None,
+        }
+    }
+
+    pub fn set_endpoint(&mut self, endpoint: &str) {
+        let endpoint = endpoint.trim().trim_end_matches('/').to_string();
+
+        match self {
+            DataSourceSettings::Overpass(source) => source.endpoint = endpoint,
+            DataSourceSettings::OsmApi(source) => source.endpoint = endpoint,
+            DataSourceSettings::LocalFile(_) => (),
+        }
+    }
+
+    /// The download format, for sources that download anything.
+    pub fn format(&self) -> Option<DataFormat> {
+        match self {
+            DataSourceSettings::Overpass(source) => Some(source.format),
+            DataSourceSettings::OsmApi(source) => Some(source.format),
+            DataSourceSettings::LocalFile(_) => None,
+        }
+    }
+
+    pub fn set_format(&mut self, format: DataFormat) {
+        match self {
+            DataSourceSettings::Overpass(source) => source.format = format,
+            DataSourceSettings::OsmApi(source) => source.format = format,
+            DataSourceSettings::LocalFile(_) => (),
+        }
+    }
+
+    pub fn source(&self) -> &dyn DataSource {
+        match self {
+            DataSourceSettings::Overpass(source) => source,
+            DataSourceSettings::OsmApi(source) => source,
+            DataSourceSettings::LocalFile(source) => source,
+        }
+    }
+}
+
+fn bbox_param(bbox: &BoundingBox) -> String {
+    format!(
+        "{},{},{},{}",
+        bbox.lower_left.lon, bbox.lower_left.lat, bbox.upper_right.lon, bbox.upper_right.lat
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::Coord;
+
+    fn bbox() -> BoundingBox {
+        BoundingBox {
+            lower_left: Coord {
+                lat: 60.39,
+                lon: 5.32,
+            },
+            upper_right: Coord {
+                lat: 60.4,
+                lon: 5.33,
+            },
+        }
+    }
+
+    #[test]
+    fn test_overpass_url() {
+        let source = DataSourceSettings::default();
+
+        assert_eq!(
+            source.source().map_url(&bbox(), "[bbox];way[\"highway\"];out;"),
+            Some("https://overpass-api.de/api/interpreter?data=%5Bbbox%5D%3Bway%5B%22highway%22%5D%3Bout%3B&bbox=5.32,60.39,5.33,60.4".into())
+        );
+        assert_eq!(source.source().chunk_key("query"), "query");
+    }
+
+    #[test]
+    fn test_json_format() {
+        let mut overpass = DataSourceSettings::default();
+        overpass.set_format(DataFormat::Json);
+
+        assert_eq!(
+            overpass.source().map_url(&bbox(), "[bbox];out;"),
+            Some("https://overpass-api.de/api/interpreter?data=%5Bout%3Ajson%5D%5Bbbox%5D%3Bout%3B&bbox=5.32,60.39,5.33,60.4".into())
+        );
+
+        let mut osm_api = DataSourceSettings::of_kind("osm-api").expect("Unknown data source");
+        osm_api.set_format(DataFormat::parse("json").expect("Unknown format"));
+
+        assert_eq!(
+            osm_api.source().map_url(&bbox(), ""),
+            Some("https://api.openstreetmap.org/api/0.6/map.json?bbox=5.32,60.39,5.33,60.4".into())
+        );
+    }
+
+    #[test]
+    fn test_osm_api_url() {
+        let mut source = DataSourceSettings::of_kind("osm-api").expect("Unknown data source");
+        source.set_endpoint(" https://master.apis.dev.openstreetmap.org/api/0.6/ ");
+
+        assert_eq!(
+            source.source().map_url(&bbox(), "ignored"),
+            Some(
+                "https://master.apis.dev.openstreetmap.org/api/0.6/map?bbox=5.32,60.39,5.33,60.4"
+                    .into()
+            )
+        );
+        assert_eq!(source.source().chunk_key("query"), "map");
+    }
+
+    #[test]
+    fn test_local_file() {
+        let source = DataSourceSettings::of_kind("local-file").expect("Unknown data source");
+
+        assert_eq!(source.source().map_url(&bbox(), "query"), None);
+        assert_eq!(source.endpoint(), None);
+    }
+}
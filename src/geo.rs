@@ -1,8 +1,11 @@
 use crate::bindings::GeolocationCoordinates;
-use crate::osm::{OsmDocument, OsmNode, OsmWay};
+use crate::osm::{OsmDocument, OsmId, OsmNode, OsmWay};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 const R: f64 = 6371008.8; // mean Earth radius
+const METERS_PER_DEGREE: f64 = R * std::f64::consts::PI / 180.0;
+const SEGMENT_INDEX_CELL_SIZE: f64 = 50.0;
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Coord {
@@ -19,11 +22,42 @@ impl From<GeolocationCoordinates> for Coord {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct BoundingBox {
     pub lower_left: Coord,
     pub upper_right: Coord,
 }
 
+impl BoundingBox {
+    /// The smallest bounding box around both coordinates.
+    pub fn spanning(a: &Coord, b: &Coord) -> BoundingBox {
+        BoundingBox {
+            lower_left: Coord {
+                lat: a.lat.min(b.lat),
+                lon: a.lon.min(b.lon),
+            },
+            upper_right: Coord {
+                lat: a.lat.max(b.lat),
+                lon: a.lon.max(b.lon),
+            },
+        }
+    }
+
+    pub fn contains(&self, coord: &Coord) -> bool {
+        coord.lat >= self.lower_left.lat
+            && coord.lat <= self.upper_right.lat
+            && coord.lon >= self.lower_left.lon
+            && coord.lon <= self.upper_right.lon
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.lower_left.lat <= other.upper_right.lat
+            && self.upper_right.lat >= other.lower_left.lat
+            && self.lower_left.lon <= other.upper_right.lon
+            && self.upper_right.lon >= other.lower_left.lon
+    }
+}
+
 // Formulas from https://www.movable-type.co.uk/scripts/latlong.html
 
 pub fn destination(c1: &Coord, bearing: f64, distance: f64) -> Coord {
@@ -115,6 +149,10 @@ impl Coord {
         }
     }
 
+    pub fn distance(self: &Coord, other: &Coord) -> f64 {
+        distance(self, other)
+    }
+
     fn phi(self: &Coord) -> f64 {
         self.lat.to_radians()
     }
@@ -151,6 +189,231 @@ impl OsmWay {
     }
 }
 
+struct Segment {
+    way_id: OsmId,
+    a: Coord,
+    b: Coord,
+}
+
+/// A grid over the line segments of all ways, so that nearest-way and radius queries only need to
+/// look at the segments close to the position.
+pub struct SegmentIndex {
+    cell_size: f64,
+    lat_step: f64,
+    lon_step: f64,
+    segments: Vec<Segment>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    min_cell: (i32, i32),
+    max_cell: (i32, i32),
+}
+
+impl SegmentIndex {
+    pub fn new(osm: &OsmDocument) -> SegmentIndex {
+        SegmentIndex::with_cell_size(osm, SEGMENT_INDEX_CELL_SIZE)
+    }
+
+    pub fn with_cell_size(osm: &OsmDocument, cell_size: f64) -> SegmentIndex {
+        // The longitude step is based on a single reference latitude, which is accurate enough
+        // within the few kilometers an OSM document covers.
+        let reference_lat = osm.nodes.first().map(|node| node.lat).unwrap_or(0.0);
+        let lat_step = cell_size / METERS_PER_DEGREE;
+        let lon_step = lat_step / reference_lat.to_radians().cos().max(0.01);
+
+        let mut index = SegmentIndex {
+            cell_size,
+            lat_step,
+            lon_step,
+            segments: vec![],
+            cells: HashMap::new(),
+            min_cell: (i32::MAX, i32::MAX),
+            max_cell: (i32::MIN, i32::MIN),
+        };
+
+        for way in osm.ways.iter() {
+            for line_segment in way.points(osm).windows(2) {
+                index.insert(Segment {
+                    way_id: way.id,
+                    a: line_segment[0].into(),
+                    b: line_segment[1].into(),
+                });
+            }
+        }
+
+        index
+    }
+
+    /// Finds the nearest point on each of the `k` nearest ways, ordered by distance.
+    pub fn nearest(&self, position: &Coord, k: usize) -> Vec<(Coord, f64, OsmId)> {
+        if self.segments.is_empty() || k == 0 {
+            return vec![];
+        }
+
+        let center = self.cell(position);
+        let max_ring = [
+            (center.0 - self.min_cell.0).abs(),
+            (center.0 - self.max_cell.0).abs(),
+            (center.1 - self.min_cell.1).abs(),
+            (center.1 - self.max_cell.1).abs(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0);
+
+        // Rings closer than the indexed area are empty
+        let min_ring = [
+            self.min_cell.0 - center.0,
+            center.0 - self.max_cell.0,
+            self.min_cell.1 - center.1,
+            center.1 - self.max_cell.1,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+        .max(0);
+
+        let mut visited = HashSet::new();
+        let mut nearest = HashMap::new();
+
+        for ring in min_ring..=max_ring {
+            // Far from the indexed area, a ring has more cells than there are occupied ones, so
+            // looking at every segment is cheaper than looking up every cell
+            if ring_size(ring) > self.cells.len() {
+                for i in 0..self.segments.len() {
+                    if visited.insert(i) {
+                        self.visit(i, position, &mut nearest);
+                    }
+                }
+
+                break;
+            }
+
+            for cell in ring_cells(center, ring) {
+                for &i in self.cells.get(&cell).into_iter().flatten() {
+                    if visited.insert(i) {
+                        self.visit(i, position, &mut nearest);
+                    }
+                }
+            }
+
+            // Cells further out than this ring are at least this far away
+            if nearest.len() >= k {
+                let mut distances: Vec<f64> = nearest.values().map(|(_, d)| *d).collect();
+                distances.sort_by(|x, y| x.partial_cmp(y).expect("Could not compare distances"));
+
+                if distances[k - 1] <= ring as f64 * self.cell_size {
+                    break;
+                }
+            }
+        }
+
+        let mut result = sorted(nearest);
+        result.truncate(k);
+        result
+    }
+
+    /// Finds the nearest point on each way within `radius` meters, ordered by distance.
+    pub fn within(&self, position: &Coord, radius: f64) -> Vec<(Coord, f64, OsmId)> {
+        let bbox = position.bbox(radius);
+        let (min_lat, min_lon) = self.cell(&bbox.lower_left);
+        let (max_lat, max_lon) = self.cell(&bbox.upper_right);
+        let mut visited = HashSet::new();
+        let mut nearest = HashMap::new();
+
+        for i in min_lat..=max_lat {
+            for j in min_lon..=max_lon {
+                for &segment in self.cells.get(&(i, j)).into_iter().flatten() {
+                    if visited.insert(segment) {
+                        self.visit(segment, position, &mut nearest);
+                    }
+                }
+            }
+        }
+
+        nearest.retain(|_, (_, distance)| *distance <= radius);
+        sorted(nearest)
+    }
+
+    fn insert(&mut self, segment: Segment) {
+        let (a, b) = (self.cell(&segment.a), self.cell(&segment.b));
+        let i = self.segments.len();
+
+        for lat in a.0.min(b.0)..=a.0.max(b.0) {
+            for lon in a.1.min(b.1)..=a.1.max(b.1) {
+                self.cells.entry((lat, lon)).or_default().push(i);
+            }
+        }
+
+        self.min_cell = (
+            self.min_cell.0.min(a.0).min(b.0),
+            self.min_cell.1.min(a.1).min(b.1),
+        );
+        self.max_cell = (
+            self.max_cell.0.max(a.0).max(b.0),
+            self.max_cell.1.max(a.1).max(b.1),
+        );
+        self.segments.push(segment);
+    }
+
+    fn visit(&self, i: usize, position: &Coord, nearest: &mut HashMap<OsmId, (Coord, f64)>) {
+        let segment = &self.segments[i];
+        let point = nearest_point(&segment.a, &segment.b, position);
+        let distance = distance(position, &point);
+
+        match nearest.get(&segment.way_id) {
+            Some((_, d)) if *d <= distance => {}
+            _ => {
+                nearest.insert(segment.way_id, (point, distance));
+            }
+        }
+    }
+
+    fn cell(&self, coord: &Coord) -> (i32, i32) {
+        (
+            (coord.lat / self.lat_step).floor() as i32,
+            (coord.lon / self.lon_step).floor() as i32,
+        )
+    }
+}
+
+fn ring_size(ring: i32) -> usize {
+    if ring == 0 {
+        1
+    } else {
+        8 * ring as usize
+    }
+}
+
+fn ring_cells(center: (i32, i32), ring: i32) -> Vec<(i32, i32)> {
+    if ring == 0 {
+        return vec![center];
+    }
+
+    let (lat, lon) = center;
+    let mut cells = vec![];
+
+    for i in -ring..=ring {
+        cells.push((lat - ring, lon + i));
+        cells.push((lat + ring, lon + i));
+    }
+
+    for i in (1 - ring)..ring {
+        cells.push((lat + i, lon - ring));
+        cells.push((lat + i, lon + ring));
+    }
+
+    cells
+}
+
+fn sorted(nearest: HashMap<OsmId, (Coord, f64)>) -> Vec<(Coord, f64, OsmId)> {
+    let mut result: Vec<(Coord, f64, OsmId)> = nearest
+        .into_iter()
+        .map(|(way_id, (point, distance))| (point, distance, way_id))
+        .collect();
+
+    result.sort_by(|(_, x, _), (_, y, _)| x.partial_cmp(y).expect("Could not compare distances"));
+    result
+}
+
 impl From<&OsmNode> for Coord {
     fn from(node: &OsmNode) -> Self {
         Coord {
@@ -213,6 +476,15 @@ mod tests {
         assert_approx_eq!(e.lon, -1.72, 0.001);
     }
 
+    #[test]
+    fn test_bbox_intersects() {
+        let bergen = BERGEN.bbox(1000.0);
+
+        assert!(bergen.intersects(&destination(&BERGEN, 45.0, 1500.0).bbox(1000.0)));
+        assert!(!bergen.intersects(&destination(&BERGEN, 45.0, 3000.0).bbox(1000.0)));
+        assert!(!bergen.intersects(&TRONDHEIM.bbox(1000.0)));
+    }
+
     #[test]
     fn test_along_track_distance_aalesund() {
         assert_approx_eq!(
@@ -240,6 +512,126 @@ mod tests {
         assert_approx_eq!(nearest_point.lon, destination.lon, 0.001);
     }
 
+    fn grid_document() -> OsmDocument {
+        // Three parallel east-west ways, roughly 111 m apart
+        OsmDocument::from_xml(
+            r#"<osm>
+  <node id="1" lat="60.000" lon="5.000"/>
+  <node id="2" lat="60.000" lon="5.010"/>
+  <node id="3" lat="60.001" lon="5.000"/>
+  <node id="4" lat="60.001" lon="5.010"/>
+  <node id="5" lat="60.002" lon="5.000"/>
+  <node id="6" lat="60.002" lon="5.005"/>
+  <node id="7" lat="60.002" lon="5.010"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/></way>
+  <way id="11"><nd ref="3"/><nd ref="4"/></way>
+  <way id="12"><nd ref="5"/><nd ref="6"/><nd ref="7"/></way>
+</osm>"#,
+        )
+        .expect("Unable to deserialize the OSM data")
+    }
+
+    #[test]
+    fn test_segment_index_nearest() {
+        let osm = grid_document();
+        let index = SegmentIndex::new(&osm);
+        let position = Coord {
+            lat: 60.0018,
+            lon: 5.006,
+        };
+
+        let nearest = index.nearest(&position, 2);
+        assert_eq!(
+            nearest.iter().map(|(_, _, id)| *id).collect::<Vec<_>>(),
+            vec![12, 11]
+        );
+        assert_approx_eq!(nearest[0].1, 22.2, 0.1);
+        assert_approx_eq!(nearest[0].0.lat, 60.002, 0.0001);
+        assert_approx_eq!(nearest[0].0.lon, 5.006, 0.0001);
+    }
+
+    #[test]
+    fn test_segment_index_nearest_far_away() {
+        let osm = grid_document();
+        let index = SegmentIndex::new(&osm);
+        let position = Coord {
+            lat: 59.99,
+            lon: 5.005,
+        };
+
+        let nearest = index.nearest(&position, 1);
+        assert_eq!(nearest.len(), 1);
+        assert_eq!(nearest[0].2, 10);
+        assert_approx_eq!(nearest[0].1, 1111.9, 0.1);
+    }
+
+    #[test]
+    fn test_segment_index_nearest_very_far_away() {
+        let osm = grid_document();
+        let index = SegmentIndex::new(&osm);
+
+        // About 1000 km south, which would be some 20 000 rings of cells
+        let position = Coord {
+            lat: 51.0,
+            lon: 5.005,
+        };
+
+        let nearest = index.nearest(&position, 2);
+        assert_eq!(
+            nearest.iter().map(|(_, _, id)| *id).collect::<Vec<_>>(),
+            vec![10, 11]
+        );
+        assert_approx_eq!(
+            nearest[0].1,
+            distance(
+                &position,
+                &Coord {
+                    lat: 60.0,
+                    lon: 5.005
+                }
+            ),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_segment_index_matches_linear_scan() {
+        let osm = grid_document();
+        let index = SegmentIndex::with_cell_size(&osm, 20.0);
+        let position = Coord {
+            lat: 60.0013,
+            lon: 5.0123,
+        };
+
+        let linear = osm
+            .ways
+            .iter()
+            .map(|way| (way.id, way.distance(&position, &osm)))
+            .min_by(|(_, x), (_, y)| x.partial_cmp(y).expect("Could not compare distances"))
+            .expect("No ways");
+
+        let (_, distance, way_id) = index.nearest(&position, 1)[0];
+        assert_eq!(way_id, linear.0);
+        assert_approx_eq!(distance, linear.1, 0.001);
+    }
+
+    #[test]
+    fn test_segment_index_within() {
+        let osm = grid_document();
+        let index = SegmentIndex::new(&osm);
+        let position = Coord {
+            lat: 60.0005,
+            lon: 5.005,
+        };
+
+        let within = index.within(&position, 60.0);
+        assert_eq!(
+            within.iter().map(|(_, _, id)| *id).collect::<Vec<_>>(),
+            vec![10, 11]
+        );
+        assert!(index.within(&position, 30.0).is_empty());
+    }
+
     #[test]
     fn test_nearest_point_stavanger() {
         let nearest_point = nearest_point(&BERGEN, &TRONDHEIM, &STAVANGER);
@@ -1,47 +1,180 @@
+use alerts::{Alert, AlertContext, AlertNotification};
+use auth::PendingLogin;
 use bindings::GeolocationPosition;
 use cfg_if::cfg_if;
-use geo::{destination, BoundingBox, Coord};
+use chunk_store::StoredChunk;
+use data_source::{DataFormat, DataSourceSettings};
+use geo::{destination, BoundingBox, Coord, SegmentIndex};
 use js_sys::Date;
 use leaflet::{LayerGroup, Map};
 use log::{error, info, warn};
-use model::{Model, Note, NoteId, OAuth2Response, Route, User};
-use osm::OsmDocument;
+use model::{
+    LocalOsmData, LocalOsmFile, Model, Note, NoteId, OAuth2Response, OsmNoteAction,
+    OsmNoteActionKind, Route, User, WayEditTarget,
+};
+use osm::{
+    changeset_xml, DiffResult, OsmChange, OsmDocument, OsmId, OsmMemberType, OsmNote,
+    OsmNoteStatus, OsmTag, OsmWay,
+};
+use outbox::{Outbox, OutboxItem, UploadState};
+use overpass::FeaturePreset;
+use poi::{PoiDraft, PoiPreset};
 use rand::prelude::*;
 use seed::{prelude::*, *};
+use tag_edit::{conflicting_keys, diff_tags, TagChange, WayEdit};
+use track::{iso_time, TraceVisibility, TrackPoint};
 use urlencoding::encode;
-use web_sys::{Element, PositionOptions, WakeLock, WakeLockSentinel, WakeLockType};
+use web_sys::{
+    AudioContext, Blob, BlobPropertyBag, Element, FormData, HtmlAnchorElement, Notification,
+    NotificationOptions, NotificationPermission, PositionOptions, ServiceWorkerRegistration,
+    WakeLock, WakeLockSentinel, WakeLockType,
+};
 
 use crate::model::UserResponse;
 
+mod alerts;
+mod auth;
 mod bindings;
+mod chunk_store;
+#[cfg(test)]
+mod counting_allocator;
+mod data_source;
 mod geo;
 mod map;
 mod model;
 mod osm;
-
+mod outbox;
+mod overpass;
+mod pbf;
+mod poi;
+mod tag_edit;
+mod track;
+
+const ACCESS_TOKEN_STORAGE_KEY: &str = "access-token";
+const LOGIN_STORAGE_KEY: &str = "pending-login";
 const NOTE_STORAGE_KEY: &str = "notes";
+const OUTBOX_STORAGE_KEY: &str = "outbox";
+const QUERY_PROFILES_STORAGE_KEY: &str = "query-profiles";
+const DATA_SOURCE_STORAGE_KEY: &str = "data-source";
+const CHUNK_STORE_SETTINGS_STORAGE_KEY: &str = "chunk-store-settings";
+const TRACK_STORAGE_KEY: &str = "track";
+const PENDING_CHANGES_STORAGE_KEY: &str = "pending-changes";
+const ALERT_SETTINGS_STORAGE_KEY: &str = "alert-settings";
+const ALERT_HISTORY_STORAGE_KEY: &str = "alert-history";
+const ALERT_HISTORY_LENGTH: usize = 50;
+const NEARBY_WAY_RADIUS: f64 = 30.0;
+const TRACK_SAVE_INTERVAL: f64 = 30_000.0;
 
 enum Msg {
     UrlChanged(subs::UrlChanged),
     DownloadOsmChunk,
     InvalidateMapSize,
     NoteChanged(String),
-    OsmMapFetched(fetch::Result<String>),
+    OsmMapFetched(BoundingBox, String, fetch::Result<String>),
+    OsmNotesFetched(fetch::Result<String>),
+    OsmNoteCommentChanged(String),
+    QueueOsmNoteAction(OsmNoteActionKind),
+    ProcessOutbox,
+    RetryOutbox,
+    OutboxEntrySent(u32, fetch::Result<String>),
+    DismissOsmError,
+    LoadStoredChunks(BoundingBox),
+    StoredChunksLoaded(Result<Vec<StoredChunk>, JsValue>),
+    ChunkStored(Result<(), JsValue>),
+    ChunkStoreUsageFetched(Result<(usize, usize), JsValue>),
+    ChunkStoreQuotaChanged(String),
+    ChunkStoreExpiryChanged(String),
+    ClearChunkStore,
+    DataSourceKindChanged(String),
+    DataSourceEndpointChanged(String),
+    DataFormatChanged(String),
+    LocalOsmFilePicked(web_sys::File),
+    LocalOsmFileRead(String, Result<LocalOsmData, JsValue>),
+    SelectQueryProfile(String),
+    QueryPresetToggled(FeaturePreset),
+    QueryFiltersChanged(String),
+    QueryProfileNameChanged(String),
+    SaveQueryProfile,
+    DeleteQueryProfile,
     OsmUserFetched(fetch::Result<User>),
-    OsmNotePosted(fetch::Result<NoteId>),
+    LogIn,
+    LogOut,
+    OsmTokenRevoked(fetch::Result<()>),
+    SessionExpired,
+    DismissSessionExpired,
     OsmAuthenticated(fetch::Result<String>),
-    Position(Coord),
+    Position(TrackPoint),
     Locate(Coord),
     RandomWalk,
     SaveNote,
     NewNote,
     EditNote(NoteId),
     UploadNote(NoteId),
+    UploadAllNotes,
     DeleteNote(NoteId),
-    SetMap((Map, LayerGroup, LayerGroup, LayerGroup)),
+    MapClicked(Coord),
+    NewPoi,
+    PoiPresetChanged(String),
+    PoiTagsChanged(String),
+    PoiCommentChanged(String),
+    PoiAtPosition,
+    PickPoiLocation,
+    CancelPoiPlacement,
+    DiscardPoi,
+    UploadPoi,
+    SavePoiForLater,
+    PoiUploaded(OsmChange, fetch::Result<(OsmId, DiffResult)>),
+    WayTagsChanged(String),
+    WayEditCommentChanged(String),
+    UploadWayEdit,
+    SaveWayEditForLater,
+    LatestWayFetched(WayEditTarget, fetch::Result<String>),
+    WayEditUploaded(OsmChange, fetch::Result<(OsmId, DiffResult)>),
+    ConflictingWayFetched(fetch::Result<String>),
+    RebaseWayEdit,
+    DiscardWayEdit,
+    PendingCommentChanged(String),
+    UploadPendingChanges,
+    PendingChangesUploaded(OsmChange, fetch::Result<(OsmId, DiffResult)>),
+    ExportPendingChanges,
+    OsmChangeFilePicked(web_sys::File),
+    OsmChangeFileRead(String, Result<String, JsValue>),
+    DiscardPendingChanges,
+    AlertRuleToggled(usize),
+    DeleteAlertRule(usize),
+    AlertRuleNameChanged(String),
+    AlertRuleChanged(String),
+    AddAlertRule,
+    AlertNotificationToggled(AlertNotification),
+    Tapped,
+    NotificationPermissionAnswered(NotificationPermission),
+    AlertRepeatChanged(String),
+    DismissAlert,
+    NoteFromAlert(usize),
+    ClearAlertHistory,
+    SetMap(
+        (
+            Map,
+            LayerGroup,
+            LayerGroup,
+            LayerGroup,
+            LayerGroup,
+            LayerGroup,
+        ),
+    ),
     FlipTrackPosition,
     FlipWakeLock,
     KeepWakeLockSentinel(WakeLockSentinel),
+    StartRecording,
+    StopRecording,
+    SaveRecordedTrack,
+    ExportTrack,
+    ClearTrack,
+    TraceDescriptionChanged(String),
+    TraceTagsChanged(String),
+    TraceVisibilityChanged(String),
+    UploadTrack,
+    OsmTracePosted(fetch::Result<u64>),
 }
 
 #[wasm_bindgen(start)]
@@ -67,13 +200,36 @@ fn init(url: Url, orders: &mut impl Orders<Msg>) -> Model {
         None
     };
 
+    let (app, msg_mapper) = (orders.clone_app(), orders.msg_mapper());
+    let map_click_callback = move |position| {
+        app.update(msg_mapper(Msg::MapClicked(position)));
+    };
+
     orders
         .subscribe(Msg::UrlChanged) // Handle route changes.
         .notify(subs::UrlChanged(url.clone())) // Handle initial route.
         .after_next_render(move |_| {
-            Msg::SetMap(map::init(track_position_callback, wake_lock_callback))
+            Msg::SetMap(map::init(
+                track_position_callback,
+                wake_lock_callback,
+                map_click_callback,
+            ))
         }); // Cannot initialize Leaflet until the map element has rendered.
 
+    // Upload what was queued while offline
+    orders.stream(streams::window_event(Ev::Online, |_| Msg::RetryOutbox));
+
+    // Save the latest track points when Surway goes to the background, as it may not come back
+    orders.stream(streams::document_event(Ev::VisibilityChange, |_| {
+        Msg::SaveRecordedTrack
+    }));
+
+    register_service_worker();
+
+    // Browsers only let a page start playing sounds from a user gesture
+    orders.stream(streams::document_event(Ev::Click, |_| Msg::Tapped));
+    orders.send_msg(Msg::ProcessOutbox);
+
     // TODO: Handle like any other route
     if url.search().contains_key("random_walk") {
         orders.stream(streams::interval(5000, || Msg::RandomWalk));
@@ -86,15 +242,32 @@ fn init(url: Url, orders: &mut impl Orders<Msg>) -> Model {
         lon: rng.gen_range(-180.0..180.0),
     };
 
+    // Check that the login from an earlier session is still valid
+    let access_token: Option<String> = LocalStorage::get(ACCESS_TOKEN_STORAGE_KEY).ok();
+
+    if let Some(access_token) = access_token.clone() {
+        orders.perform_cmd(async move {
+            Msg::OsmUserFetched(send_osm_user_request(&access_token).await)
+        });
+    }
+
+    let mut outbox: Outbox = LocalStorage::get(OUTBOX_STORAGE_KEY).unwrap_or_default();
+    outbox.reset_interrupted();
+
     Model {
         route: Route::from(url),
-        access_token: None,
+        access_token,
+        session_expired: false,
         user: None,
         map: None,
         topology_layer_group: None,
         position_layer_group: None,
         notes_layer_group: None,
+        osm_notes_layer_group: None,
+        track_layer_group: None,
         osm: OsmDocument::new(),
+        segment_index: SegmentIndex::new(&OsmDocument::new()),
+        osm_error: None,
         position,
         nearest_way_id: None,
         start_distance: None,
@@ -104,10 +277,49 @@ fn init(url: Url, orders: &mut impl Orders<Msg>) -> Model {
         osm_chunk_position: None,
         osm_chunk_radius: 500.0,
         osm_chunk_trigger_factor: 0.8,
+        osm_eviction_radius: 2000.0,
+        data_source: LocalStorage::get(DATA_SOURCE_STORAGE_KEY).unwrap_or_default(),
+        local_osm_file: None,
+        query_profiles: LocalStorage::get(QUERY_PROFILES_STORAGE_KEY).unwrap_or_default(),
+        new_query_profile_name: "".into(),
+        stored_chunks_loaded: false,
+        chunk_store_settings: LocalStorage::get(CHUNK_STORE_SETTINGS_STORAGE_KEY)
+            .unwrap_or_default(),
+        chunk_store_usage: None,
         notes: LocalStorage::get(NOTE_STORAGE_KEY).unwrap_or_default(),
+        osm_notes: vec![],
+        osm_note_comment: "".into(),
+        outbox,
+        poi: None,
+        placing_poi: false,
+        poi_upload: None,
+        way_edit: None,
+        way_edit_upload: None,
+        way_conflict: None,
+        pending_changes: LocalStorage::get::<String>(PENDING_CHANGES_STORAGE_KEY)
+            .ok()
+            .and_then(|xml| OsmChange::from_xml(&xml).ok())
+            .unwrap_or_default(),
+        pending_comment: "".into(),
+        pending_upload: None,
+        alert_settings: LocalStorage::get(ALERT_SETTINGS_STORAGE_KEY).unwrap_or_default(),
+        alert_monitor: Default::default(),
+        alert: None,
+        alert_history: LocalStorage::get(ALERT_HISTORY_STORAGE_KEY).unwrap_or_default(),
+        new_alert_rule_name: "".into(),
+        new_alert_rule: "".into(),
+        new_alert_rule_error: None,
+        audio_context: None,
         new_note: "".into(),
         note_id: None,
         wake_lock_sentinel: None,
+        track: LocalStorage::get(TRACK_STORAGE_KEY).unwrap_or_default(),
+        track_saved_at: 0.0,
+        track_unsaved: false,
+        trace_description: "".into(),
+        trace_tags: "surway".into(),
+        trace_visibility: TraceVisibility::Trackable,
+        trace_upload: None,
     }
 }
 
@@ -118,22 +330,71 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             let route = Route::from(url);
             info!("Route: {:?}", route);
 
-            if let Route::Callback(code) = route.clone() {
-                info!("Auth code: {}", code);
-                orders.perform_cmd(async move {
-                    Msg::OsmAuthenticated(send_osm_token_request(&code).await)
-                });
+            if let Route::Callback { code, state } = route.clone() {
+                let pending_login: Option<PendingLogin> =
+                    SessionStorage::get(LOGIN_STORAGE_KEY).ok();
+                SessionStorage::remove(LOGIN_STORAGE_KEY).expect("Unable to remove pending login");
+
+                match (code, pending_login) {
+                    // The state makes sure this is the login we started, and not a forged one
+                    (Some(code), Some(login)) if state.as_ref() == Some(&login.state) => {
+                        orders.perform_cmd(async move {
+                            Msg::OsmAuthenticated(send_osm_token_request(&code, &login).await)
+                        });
+                    }
+                    _ => error!("Ignoring an OSM login we didn't start."),
+                }
 
                 model.route = Route::Main;
             } else {
+                if route == Route::Settings {
+                    orders.perform_cmd(async {
+                        Msg::ChunkStoreUsageFetched(chunk_store::usage().await)
+                    });
+                }
+
+                if route == Route::NewPoi && model.poi.is_none() && model.poi_upload.is_none() {
+                    new_poi(model);
+                }
+
+                if let Route::EditWay(way_id) = route {
+                    if model.way_edit.as_ref().map(|edit| edit.way_id) != Some(way_id) {
+                        model.way_edit = model.osm.way(way_id).map(WayEdit::new);
+                        model.way_edit_upload = None;
+                        model.way_conflict = None;
+                    }
+                }
+
                 model.route = route;
             }
         }
 
         Msg::DownloadOsmChunk => {
             let bbox = model.position.bbox(model.osm_chunk_radius);
-            orders
-                .perform_cmd(async move { Msg::OsmMapFetched(send_osm_map_request(&bbox).await) });
+            let notes_bbox = bbox.clone();
+            let query = model.query_profiles.active().query();
+
+            if let Some(url) = model.data_source.source().map_url(&bbox, &query) {
+                // Show what we have from earlier sessions while waiting for fresh data
+                if !model.stored_chunks_loaded {
+                    model.stored_chunks_loaded = true;
+                    orders.send_msg(Msg::LoadStoredChunks(bbox.clone()));
+                }
+
+                let chunk_key = model.chunk_key();
+
+                orders.perform_cmd(async move {
+                    let result = send_osm_map_request(url).await;
+                    Msg::OsmMapFetched(bbox, chunk_key, result)
+                });
+
+                // Only along with the map data, as a local file is for surveying offline
+                orders.perform_cmd(async move {
+                    Msg::OsmNotesFetched(send_osm_notes_request(&notes_bbox).await)
+                });
+            } else if !model.has_whole_osm_file() {
+                load_local_osm_file(model, orders);
+            }
         }
 
         Msg::InvalidateMapSize => {
@@ -146,17 +407,31 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             model.new_note = text;
         }
 
-        Msg::OsmMapFetched(Ok(response_data)) => {
-            model.osm = quick_xml::de::from_str(&response_data)
-                .expect("Unable to deserialize the OSM data");
-
-            // If we haven't calculated nearest way yet, do it now
-            update_position(model.position, model, orders);
+        Msg::OsmMapFetched(_, chunk_key, _) if chunk_key != model.chunk_key() => {
+            info!("Ignoring OSM data downloaded for another data source or map profile.");
+        }
 
-            map::render_topology_and_position(model);
+        Msg::OsmMapFetched(bbox, chunk_key, Ok(response_data)) => {
+            match OsmDocument::parse(&response_data) {
+                Ok(osm) => {
+                    model.osm_error = None;
+                    load_osm_document(osm, model, orders);
+
+                    let chunk = StoredChunk::new(bbox, chunk_key, Date::now(), response_data);
+                    let settings = model.chunk_store_settings;
+                    orders.perform_cmd(async move {
+                        Msg::ChunkStored(chunk_store::save(chunk, settings, Date::now()).await)
+                    });
+                }
+                Err(osm_error) => {
+                    // Keep showing the data we already have
+                    error!("Loading OSM data failed: {}", osm_error);
+                    model.osm_error = Some(osm_error);
+                }
+            }
         }
 
-        Msg::OsmMapFetched(Err(fetch_error)) => {
+        Msg::OsmMapFetched(bbox, _, Err(fetch_error)) => {
             if let FetchError::StatusError(status) = &fetch_error {
                 if status.code == 429 || status.code == 504 {
                     const SECONDS: u32 = 10;
@@ -166,16 +441,394 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 }
             }
             error!("Fetching OSM data failed: {:#?}", fetch_error);
+
+            // We might be offline, so fall back to what we downloaded earlier
+            orders.send_msg(Msg::LoadStoredChunks(bbox));
+        }
+
+        Msg::OsmNotesFetched(Ok(response_data)) => match OsmNote::list_from_xml(&response_data) {
+            Ok(notes) => {
+                info!("Fetched {} OSM notes.", notes.len());
+                model.merge_osm_notes(notes);
+                map::render_osm_notes(model);
+            }
+            Err(osm_error) => error!("Loading OSM notes failed: {}", osm_error),
+        },
+
+        Msg::OsmNotesFetched(Err(fetch_error)) => {
+            error!("Fetching OSM notes failed: {:#?}", fetch_error);
+        }
+
+        Msg::OsmNoteCommentChanged(text) => {
+            model.osm_note_comment = text;
+        }
+
+        Msg::QueueOsmNoteAction(kind) => {
+            if let Route::OsmNote(note_id) = model.route {
+                let action = OsmNoteAction {
+                    note_id,
+                    kind,
+                    text: std::mem::take(&mut model.osm_note_comment),
+                };
+
+                model
+                    .outbox
+                    .push(OutboxItem::NoteAction(action), Date::now());
+                save_outbox(model);
+                orders.send_msg(Msg::ProcessOutbox);
+            }
+        }
+
+        Msg::ProcessOutbox => {
+            if !window().navigator().on_line() {
+                info!("Offline. Keeping the outbox until we are online again.");
+                return;
+            }
+
+            let now = Date::now();
+
+            if let Some(entry) = model.outbox.start_next(now) {
+                let access_token = model.access_token.clone();
+
+                match &entry.item {
+                    OutboxItem::Note(note_id) => {
+                        match model.notes.iter().find(|note| note.id == *note_id) {
+                            // Posting it again would make a duplicate note
+                            Some(note) if note.uploaded => {
+                                match note.osm_note_id {
+                                    Some(osm_note_id) => {
+                                        model.outbox.succeeded(entry.id, osm_note_id);
+                                    }
+                                    None => model.outbox.remove_note(*note_id),
+                                }
+
+                                save_outbox(model);
+                                orders.send_msg(Msg::ProcessOutbox);
+                            }
+                            // We were logged in when the note was queued, but aren't anymore
+                            Some(note) if access_token.is_none() && !note.anonymous => {
+                                model.outbox.failed(
+                                    entry.id,
+                                    "Log in to OSM, or upload again to post anonymously.".into(),
+                                    false,
+                                    now,
+                                );
+                                orders.send_msg(Msg::ProcessOutbox);
+                            }
+                            Some(note) => {
+                                let note = note.clone();
+                                orders.perform_cmd(async move {
+                                    Msg::OutboxEntrySent(
+                                        entry.id,
+                                        send_osm_note_request(note, access_token).await,
+                                    )
+                                });
+                            }
+                            None => {
+                                model.outbox.remove_note(*note_id);
+                                orders.send_msg(Msg::ProcessOutbox);
+                            }
+                        }
+                    }
+                    OutboxItem::NoteAction(action) => match access_token {
+                        Some(access_token) => {
+                            let action = action.clone();
+                            orders.perform_cmd(async move {
+                                Msg::OutboxEntrySent(
+                                    entry.id,
+                                    send_osm_note_action_request(&access_token, &action).await,
+                                )
+                            });
+                        }
+                        None => {
+                            model.outbox.failed(
+                                entry.id,
+                                "Log in to OSM to send this.".into(),
+                                false,
+                                now,
+                            );
+                            orders.send_msg(Msg::ProcessOutbox);
+                        }
+                    },
+                }
+
+                save_outbox(model);
+            } else if let Some(retry_at) = model.outbox.next_retry_at() {
+                let delay = (retry_at - now).max(0.0) as u32;
+                orders.perform_cmd(cmds::timeout(delay, || Msg::ProcessOutbox));
+            }
+        }
+
+        Msg::RetryOutbox => {
+            model.outbox.retry_all(Date::now());
+            orders.send_msg(Msg::ProcessOutbox);
+        }
+
+        Msg::OutboxEntrySent(id, Ok(response_data)) => {
+            match OsmNote::list_from_xml(&response_data) {
+                Ok(notes) if !notes.is_empty() => match model.outbox.succeeded(id, notes[0].id) {
+                    Some(OutboxItem::Note(note_id)) => {
+                        if let Some(note) = model.notes.iter_mut().find(|note| note.id == note_id) {
+                            note.uploaded = true;
+                            note.osm_note_id = Some(notes[0].id);
+
+                            LocalStorage::insert(NOTE_STORAGE_KEY, &model.notes)
+                                .expect("Unable to save note to LocalStorage");
+                        }
+                    }
+                    Some(OutboxItem::NoteAction(_)) => {
+                        model.merge_osm_notes(notes);
+                        map::render_osm_notes(model);
+                    }
+                    None => {}
+                },
+                Ok(_) => model
+                    .outbox
+                    .failed(id, "OSM returned no note".into(), false, Date::now()),
+                Err(osm_error) => {
+                    model
+                        .outbox
+                        .failed(id, osm_error.to_string(), false, Date::now())
+                }
+            }
+
+            save_outbox(model);
+            orders.send_msg(Msg::ProcessOutbox);
+        }
+
+        Msg::OutboxEntrySent(id, Err(fetch_error)) => {
+            warn!("Uploading from the outbox failed: {:#?}", fetch_error);
+
+            if is_unauthorized(&fetch_error) {
+                orders.send_msg(Msg::SessionExpired);
+            }
+
+            model.outbox.failed(
+                id,
+                describe_fetch_error(&fetch_error),
+                is_transient(&fetch_error),
+                Date::now(),
+            );
+
+            save_outbox(model);
+            orders.send_msg(Msg::ProcessOutbox);
+        }
+
+        Msg::LoadStoredChunks(bbox) => {
+            let chunk_key = model.chunk_key();
+            let settings = model.chunk_store_settings;
+            orders.perform_cmd(async move {
+                Msg::StoredChunksLoaded(
+                    chunk_store::load(bbox, chunk_key, settings, Date::now()).await,
+                )
+            });
+        }
+
+        Msg::StoredChunksLoaded(Ok(chunks)) => {
+            info!("Loaded {} stored OSM chunks.", chunks.len());
+
+            for chunk in chunks {
+                match OsmDocument::parse(&chunk.data) {
+                    Ok(osm) => load_osm_document(osm, model, orders),
+                    Err(osm_error) => warn!("Skipping stored OSM chunk: {}", osm_error),
+                }
+            }
+        }
+
+        Msg::StoredChunksLoaded(Err(error)) => {
+            error!("Loading stored OSM chunks failed: {:?}", error);
+        }
+
+        Msg::ChunkStored(result) => {
+            if let Err(error) = result {
+                error!("Storing OSM chunk failed: {:?}", error);
+            }
+        }
+
+        Msg::ChunkStoreUsageFetched(Ok(usage)) => {
+            model.chunk_store_usage = Some(usage);
+        }
+
+        Msg::ChunkStoreUsageFetched(Err(error)) => {
+            error!("Fetching OSM chunk storage usage failed: {:?}", error);
+        }
+
+        Msg::ChunkStoreQuotaChanged(megabytes) => {
+            if let Ok(megabytes) = megabytes.parse::<usize>() {
+                model.chunk_store_settings.quota_bytes = megabytes * 1024 * 1024;
+                save_chunk_store_settings(model);
+            }
+        }
+
+        Msg::ChunkStoreExpiryChanged(days) => {
+            if let Ok(days) = days.parse::<f64>() {
+                model.chunk_store_settings.expiry_days = days;
+                save_chunk_store_settings(model);
+            }
+        }
+
+        Msg::ClearChunkStore => {
+            orders.perform_cmd(async {
+                if let Err(error) = chunk_store::clear().await {
+                    error!("Clearing OSM chunk storage failed: {:?}", error);
+                }
+
+                Msg::ChunkStoreUsageFetched(chunk_store::usage().await)
+            });
+        }
+
+        Msg::DataSourceKindChanged(kind) => {
+            if let Some(data_source) = DataSourceSettings::of_kind(&kind) {
+                model.data_source = data_source;
+                model.local_osm_file = None;
+                save_data_source(model);
+                reload_osm_data(model, orders);
+            }
+        }
+
+        Msg::DataSourceEndpointChanged(endpoint) => {
+            model.data_source.set_endpoint(&endpoint);
+            save_data_source(model);
+            reload_osm_data(model, orders);
+        }
+
+        Msg::DataFormatChanged(format) => {
+            if let Some(format) = DataFormat::parse(&format) {
+                model.data_source.set_format(format);
+                save_data_source(model);
+                reload_osm_data(model, orders);
+            }
+        }
+
+        Msg::LocalOsmFilePicked(file) => {
+            let name = file.name();
+            orders.perform_cmd(async move {
+                let result = if name.ends_with(".pbf") {
+                    JsFuture::from(file.array_buffer())
+                        .await
+                        .map(|buffer| LocalOsmData::Pbf(js_sys::Uint8Array::new(&buffer).to_vec()))
+                } else {
+                    JsFuture::from(file.text())
+                        .await
+                        .map(|text| LocalOsmData::Text(text.as_string().unwrap_or_default()))
+                };
+                Msg::LocalOsmFileRead(name, result)
+            });
+        }
+
+        Msg::LocalOsmFileRead(name, Ok(data)) => {
+            info!("Read OSM file {}.", name);
+            model.data_source = DataSourceSettings::LocalFile(data_source::LocalFile {
+                name: Some(name.clone()),
+            });
+            model.local_osm_file = Some(LocalOsmFile { name, data });
+            save_data_source(model);
+            reload_osm_data(model, orders);
+        }
+
+        Msg::LocalOsmFileRead(name, Err(error)) => {
+            error!("Reading OSM file {} failed: {:?}", name, error);
+        }
+
+        Msg::SelectQueryProfile(name) => {
+            model.query_profiles.select(&name);
+            reload_osm_data(model, orders);
+        }
+
+        Msg::QueryPresetToggled(preset) => {
+            let presets = &mut model.query_profiles.active_mut().presets;
+
+            if presets.contains(&preset) {
+                presets.retain(|p| *p != preset);
+            } else {
+                presets.push(preset);
+            }
+
+            reload_osm_data(model, orders);
+        }
+
+        Msg::QueryFiltersChanged(filters) => {
+            model.query_profiles.active_mut().custom_filters =
+                filters.lines().map(String::from).collect();
+            reload_osm_data(model, orders);
+        }
+
+        Msg::QueryProfileNameChanged(name) => {
+            model.new_query_profile_name = name;
+        }
+
+        Msg::SaveQueryProfile => {
+            model
+                .query_profiles
+                .save_as(&std::mem::take(&mut model.new_query_profile_name));
+            save_query_profiles(model);
+        }
+
+        Msg::DeleteQueryProfile => {
+            model.query_profiles.delete_active();
+            reload_osm_data(model, orders);
+        }
+
+        Msg::DismissOsmError => {
+            model.osm_error = None;
+        }
+
+        Msg::LogIn => {
+            let login = PendingLogin::new();
+
+            SessionStorage::insert(LOGIN_STORAGE_KEY, &login)
+                .expect("Unable to save pending login to SessionStorage");
+
+            window()
+                .location()
+                .set_href(&login.authorize_url(&oauth2_client(), &oauth2_callback()))
+                .expect("Unable to go to the OSM login");
+        }
+
+        Msg::LogOut => {
+            if let Some(access_token) = model.access_token.clone() {
+                orders.perform_cmd(async move {
+                    Msg::OsmTokenRevoked(send_osm_revoke_request(&access_token).await)
+                });
+            }
+
+            end_session(model);
+            model.route = Route::Main;
+        }
+
+        Msg::OsmTokenRevoked(Ok(())) => {
+            info!("Logged out of OSM.");
+        }
+
+        Msg::OsmTokenRevoked(Err(fetch_error)) => {
+            error!("Revoking the OSM access token failed: {:#?}", fetch_error);
+        }
+
+        Msg::SessionExpired => {
+            warn!("The OSM access token is no longer valid.");
+            end_session(model);
+            model.session_expired = true;
+        }
+
+        Msg::DismissSessionExpired => {
+            model.session_expired = false;
         }
 
         Msg::OsmAuthenticated(Ok(access_token)) => {
-            info!("User {} successfully authenticated.", access_token);
+            info!("User successfully authenticated.");
             let request_token = access_token.clone();
+
+            LocalStorage::insert(ACCESS_TOKEN_STORAGE_KEY, &access_token)
+                .expect("Unable to save access token to LocalStorage");
+
             model.access_token = Some(access_token);
+            model.session_expired = false;
 
             orders.perform_cmd(async move {
                 Msg::OsmUserFetched(send_osm_user_request(&request_token).await)
             });
+
+            orders.send_msg(Msg::RetryOutbox);
         }
 
         Msg::OsmAuthenticated(Err(fetch_error)) => {
@@ -188,9 +841,20 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
 
         Msg::OsmUserFetched(Err(fetch_error)) => {
             error!("Fetching OSM user failed: {:#?}", fetch_error);
+
+            if is_unauthorized(&fetch_error) {
+                orders.send_msg(Msg::SessionExpired);
+            }
         }
 
-        Msg::Position(position) => {
+        Msg::Position(point) => {
+            let position = point.position;
+
+            if model.track.record(point) {
+                save_recorded_track(model);
+                map::render_track(model);
+            }
+
             update_position(position, model, orders);
         }
 
@@ -236,9 +900,14 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 position,
                 text: model.new_note.clone(),
                 uploaded: false,
+                osm_note_id: None,
+                anonymous: false,
             };
 
+            // An edited note is a new note as far as OSM is concerned
             model.notes.retain(|note| note.id != id);
+            model.outbox.remove_note(id);
+            save_outbox(model);
             model.notes.push_front(note);
 
             model.note_id = None;
@@ -269,33 +938,39 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         }
 
         Msg::UploadNote(id) => {
-            let note: Note = model
+            let is_uploaded = model
                 .notes
                 .iter()
-                .find(|note| note.id == id)
-                .unwrap_or_else(|| panic!("Did not find a note with id {}", id))
-                .clone();
+                .any(|note| note.id == id && note.uploaded);
 
-            if !note.uploaded {
-                orders.perform_cmd(async { Msg::OsmNotePosted(send_osm_note_request(note).await) });
+            if is_uploaded || !confirm_anonymous_upload(model, |note| note.id == id) {
+                return;
             }
+
+            model.outbox.push(OutboxItem::Note(id), Date::now());
+            save_outbox(model);
+            orders.send_msg(Msg::ProcessOutbox);
         }
 
-        Msg::OsmNotePosted(Ok(note_id)) => {
-            if let Some(note) = model.notes.iter_mut().find(|note| note.id == note_id) {
-                note.uploaded = true;
+        Msg::UploadAllNotes => {
+            if !confirm_anonymous_upload(model, |note| !note.uploaded) {
+                return;
+            }
 
-                LocalStorage::insert(NOTE_STORAGE_KEY, &model.notes)
-                    .expect("Unable to save note to LocalStorage");
+            let now = Date::now();
+
+            for note in model.notes.iter().filter(|note| !note.uploaded) {
+                model.outbox.push(OutboxItem::Note(note.id), now);
             }
-        }
 
-        Msg::OsmNotePosted(Err(fetch_error)) => {
-            error!("Posting OSM note failed: {:#?}", fetch_error);
+            save_outbox(model);
+            orders.send_msg(Msg::RetryOutbox);
         }
 
         Msg::DeleteNote(id) => {
             model.notes.retain(|note| note.id != id);
+            model.outbox.remove_note(id);
+            save_outbox(model);
 
             LocalStorage::insert(NOTE_STORAGE_KEY, &model.notes)
                 .expect("Unable to save note to LocalStorage");
@@ -303,80 +978,745 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             map::render_notes(model);
         }
 
-        Msg::SetMap((map, topology_layer_group, position_layer_group, notes_layer_group)) => {
-            model.map = Some(map);
-            model.topology_layer_group = Some(topology_layer_group);
-            model.position_layer_group = Some(position_layer_group);
-            model.notes_layer_group = Some(notes_layer_group);
-            map::set_view(model);
-            map::render_topology_and_position(model);
-            map::render_notes(model);
+        Msg::MapClicked(position) => {
+            if model.placing_poi {
+                model.placing_poi = false;
+
+                if let Some(poi) = &mut model.poi {
+                    poi.position = position;
+                }
+
+                map::render_position(model);
+                go_to("new-poi");
+            }
         }
 
-        Msg::FlipTrackPosition => {
-            model.track_position = !model.track_position;
-            flip_track_position_icon();
+        Msg::NewPoi => {
+            new_poi(model);
         }
 
-        Msg::FlipWakeLock => {
-            if let Some(sentinel) = &model.wake_lock_sentinel {
-                let _promise = sentinel.release();
-                model.wake_lock_sentinel = None;
-                flip_wake_lock_icon();
-            } else {
-                orders.skip().perform_cmd({
-                    async {
-                        let sentinel: WakeLockSentinel = JsCast::unchecked_into(
-                            wasm_bindgen_futures::JsFuture::from(
-                                wake_lock().request(WakeLockType::Screen),
-                            )
-                            .await
-                            .expect("Unable to get wake lock result."),
-                        );
+        Msg::PoiPresetChanged(preset) => {
+            if let (Some(poi), Some(preset)) = (&mut model.poi, PoiPreset::parse(&preset)) {
+                poi.set_preset(preset);
+                map::render_position(model);
+            }
+        }
 
-                        flip_wake_lock_icon();
-                        Msg::KeepWakeLockSentinel(sentinel)
+        Msg::PoiTagsChanged(tags) => {
+            if let Some(poi) = &mut model.poi {
+                poi.tags = tags;
+            }
+        }
+
+        Msg::PoiCommentChanged(comment) => {
+            if let Some(poi) = &mut model.poi {
+                poi.comment = comment;
+            }
+        }
+
+        Msg::PoiAtPosition => {
+            if let Some(poi) = &mut model.poi {
+                poi.position = model.position;
+                map::render_position(model);
+            }
+        }
+
+        Msg::PickPoiLocation => {
+            model.placing_poi = true;
+        }
+
+        Msg::CancelPoiPlacement => {
+            model.placing_poi = false;
+            go_to("new-poi");
+        }
+
+        Msg::DiscardPoi => {
+            model.poi = None;
+            model.poi_upload = None;
+            model.placing_poi = false;
+            map::render_position(model);
+        }
+
+        Msg::UploadPoi => {
+            if let (Some(access_token), Some(poi)) = (model.access_token.clone(), &model.poi) {
+                match poi.to_node() {
+                    Ok(node) => {
+                        let change = OsmChange::new_node(node, &model.pending_changes);
+                        let comment = poi.comment.trim().to_string();
+                        model.poi_upload = Some(UploadState::Uploading);
+
+                        orders.perform_cmd(async move {
+                            let result = upload_osm_change(&access_token, &comment, &change).await;
+                            Msg::PoiUploaded(change, result)
+                        });
                     }
-                });
+                    Err(error) => model.poi_upload = Some(UploadState::Failed(error)),
+                }
             }
         }
 
-        Msg::KeepWakeLockSentinel(sentinel) => {
-            model.wake_lock_sentinel = Some(sentinel);
+        Msg::SavePoiForLater => {
+            if let Some(poi) = &model.poi {
+                match poi.to_node() {
+                    Ok(node) => {
+                        model.pending_changes.create_node(node);
+                        model.poi = None;
+                        model.poi_upload = None;
+                        save_pending_changes(model);
+                        show_pending_changes(model, orders);
+                        go_to("edits");
+                    }
+                    Err(error) => model.poi_upload = Some(UploadState::Failed(error)),
+                }
+            }
         }
-    }
-}
 
-fn view(model: &Model) -> Node<Msg> {
-    div![
-        div![
-            C!["content"],
-            div![id!["map"],],
-            div![
-                C!["button-row"],
-                a!(
-                    C!["btn btn-primary"],
-                    attrs! {
-                        At::Href => "#notes"
-                    },
-                    "Notes"
-                ),
-                a!(
-                    C!["btn"],
-                    attrs! {
-                        At::Href => format!("https://www.openstreetmap.org/oauth2/authorize?response_type=code&client_id={}&scope=read_prefs+write_notes&redirect_uri={}&state=foo",
-                        oauth2_client(), oauth2_callback())
-                    },
-                    model
-                        .user
-                        .as_ref()
-                        .map(|u| u.name.clone())
-                        .unwrap_or_else(|| "OSM login".into())
-                )
-            ],
-            view_way(model),
-        ],
-        div![
+        Msg::PoiUploaded(change, Ok((_, diff))) => {
+            match change.uploaded(&diff).nodes.first().map(|node| node.id) {
+                Some(id) if id > 0 => {
+                    info!("Uploaded POI as node {}.", id);
+
+                    // Show the new node right away, rather than waiting for the next download
+                    model.osm.apply_upload(&change, &diff);
+                    model.poi = None;
+                    model.poi_upload = Some(UploadState::Uploaded(id));
+                    refresh_osm_data(model, orders);
+                }
+                _ => {
+                    model.poi_upload = Some(UploadState::Failed(
+                        "OSM did not return the new node".into(),
+                    ));
+                }
+            }
+        }
+
+        Msg::PoiUploaded(_, Err(fetch_error)) => {
+            error!("Uploading POI failed: {:#?}", fetch_error);
+
+            if is_unauthorized(&fetch_error) {
+                orders.send_msg(Msg::SessionExpired);
+            }
+
+            model.poi_upload = Some(UploadState::Failed(describe_fetch_error(&fetch_error)));
+        }
+
+        Msg::WayTagsChanged(tags) => {
+            if let Some(edit) = &mut model.way_edit {
+                edit.tags = tags;
+            }
+        }
+
+        Msg::WayEditCommentChanged(comment) => {
+            if let Some(edit) = &mut model.way_edit {
+                edit.comment = comment;
+            }
+        }
+
+        Msg::UploadWayEdit => {
+            if model.access_token.is_some() {
+                fetch_latest_way(model, orders, WayEditTarget::Upload);
+            }
+        }
+
+        Msg::SaveWayEditForLater => fetch_latest_way(model, orders, WayEditTarget::SaveForLater),
+
+        Msg::LatestWayFetched(target, Ok(response_data)) => {
+            let edit = match &model.way_edit {
+                Some(edit) => edit,
+                None => return,
+            };
+
+            let theirs = match OsmDocument::from_xml(&response_data) {
+                Ok(osm) => osm,
+                Err(osm_error) => {
+                    model.way_edit_upload = Some(UploadState::Failed(osm_error.to_string()));
+                    return;
+                }
+            };
+
+            let way = match theirs.way(edit.way_id) {
+                // Someone else has changed the tags since we downloaded the way
+                Some(their_way) if edit.is_outdated(their_way) => {
+                    model.way_conflict = Some(theirs);
+                    model.way_edit_upload = None;
+                    return;
+                }
+                Some(their_way) => edit.to_way(their_way),
+                None => Err("OSM did not return the way".into()),
+            };
+
+            match (way, target, model.access_token.clone()) {
+                (Ok(way), WayEditTarget::Upload, Some(access_token)) => {
+                    let mut change = OsmChange::default();
+                    change.modify_way(way);
+                    let comment = edit.comment.trim().to_string();
+
+                    orders.perform_cmd(async move {
+                        let result = upload_osm_change(&access_token, &comment, &change).await;
+                        Msg::WayEditUploaded(change, result)
+                    });
+                }
+                (Ok(_), WayEditTarget::Upload, None) => {
+                    model.way_edit_upload = Some(UploadState::Failed(
+                        "Log in to OSM to upload the edit".into(),
+                    ));
+                }
+                (Ok(way), WayEditTarget::SaveForLater, _) => {
+                    model.pending_changes.modify_way(way);
+                    model.way_edit = None;
+                    model.way_edit_upload = None;
+                    save_pending_changes(model);
+                    show_pending_changes(model, orders);
+                    go_to("edits");
+                }
+                (Err(error), _, _) => model.way_edit_upload = Some(UploadState::Failed(error)),
+            }
+        }
+
+        Msg::LatestWayFetched(_, Err(fetch_error)) => {
+            error!(
+                "Fetching the latest version of the way failed: {:#?}",
+                fetch_error
+            );
+            model.way_edit_upload = Some(UploadState::Failed(describe_fetch_error(&fetch_error)));
+        }
+
+        Msg::WayEditUploaded(change, Ok((changeset, diff))) => {
+            info!("Uploaded new way tags in changeset {}.", changeset);
+
+            model.osm.apply_upload(&change, &diff);
+
+            // An edit saved for later would be based on the old version of the way
+            model.pending_changes.remove_uploaded(&change);
+            save_pending_changes(model);
+
+            model.way_edit = None;
+            model.way_edit_upload = None;
+            refresh_osm_data(model, orders);
+            go_to("");
+        }
+
+        Msg::WayEditUploaded(_, Err(fetch_error)) => {
+            error!("Uploading way tags failed: {:#?}", fetch_error);
+
+            match &fetch_error {
+                // Someone else has changed the way since we downloaded it
+                FetchError::StatusError(status) if status.code == 409 => {
+                    if let Some(edit) = &model.way_edit {
+                        let way_id = edit.way_id;
+                        orders.perform_cmd(async move {
+                            Msg::ConflictingWayFetched(send_osm_way_request(way_id).await)
+                        });
+                    }
+                }
+                _ if is_unauthorized(&fetch_error) => {
+                    orders.send_msg(Msg::SessionExpired);
+                }
+                _ => (),
+            }
+
+            model.way_edit_upload = Some(UploadState::Failed(describe_fetch_error(&fetch_error)));
+        }
+
+        Msg::ConflictingWayFetched(Ok(response_data)) => {
+            match OsmDocument::from_xml(&response_data) {
+                Ok(osm) => {
+                    model.way_conflict = Some(osm);
+                    model.way_edit_upload = None;
+                }
+                Err(osm_error) => {
+                    model.way_edit_upload = Some(UploadState::Failed(osm_error.to_string()));
+                }
+            }
+        }
+
+        Msg::ConflictingWayFetched(Err(fetch_error)) => {
+            error!(
+                "Fetching the latest version of the way failed: {:#?}",
+                fetch_error
+            );
+            model.way_edit_upload = Some(UploadState::Failed(describe_fetch_error(&fetch_error)));
+        }
+
+        Msg::RebaseWayEdit => {
+            if let (Some(edit), Some(theirs)) = (&mut model.way_edit, model.way_conflict.take()) {
+                if let Some(way) = theirs.way(edit.way_id) {
+                    if let Err(error) = edit.rebase(way) {
+                        model.way_edit_upload = Some(UploadState::Failed(error));
+                    }
+                }
+
+                load_osm_document(theirs, model, orders);
+            }
+        }
+
+        Msg::DiscardWayEdit => {
+            model.way_edit = None;
+            model.way_edit_upload = None;
+
+            // Show their version of the way instead
+            if let Some(theirs) = model.way_conflict.take() {
+                load_osm_document(theirs, model, orders);
+            }
+        }
+
+        Msg::PendingCommentChanged(comment) => {
+            model.pending_comment = comment;
+        }
+
+        Msg::UploadPendingChanges => {
+            if let Some(access_token) = model.access_token.clone() {
+                let change = model.pending_changes.clone();
+                let comment = model.pending_comment.trim().to_string();
+                model.pending_upload = Some(UploadState::Uploading);
+
+                orders.perform_cmd(async move {
+                    let result = upload_osm_change(&access_token, &comment, &change).await;
+                    Msg::PendingChangesUploaded(change, result)
+                });
+            }
+        }
+
+        Msg::PendingChangesUploaded(change, Ok((changeset, diff))) => {
+            info!(
+                "Uploaded {} edits in changeset {}.",
+                change.len(),
+                changeset
+            );
+
+            model.osm.apply_upload(&change, &diff);
+            model.pending_changes.remove_uploaded(&change);
+            save_pending_changes(model);
+
+            model.pending_comment = "".into();
+            model.pending_upload = Some(UploadState::Uploaded(changeset));
+            refresh_osm_data(model, orders);
+        }
+
+        Msg::PendingChangesUploaded(_, Err(fetch_error)) => {
+            error!("Uploading edits failed: {:#?}", fetch_error);
+
+            if is_unauthorized(&fetch_error) {
+                orders.send_msg(Msg::SessionExpired);
+            }
+
+            let reason = match &fetch_error {
+                FetchError::StatusError(status) if status.code == 409 => {
+                    "Someone else has changed what you edited. Export your edits to review them in an editor like JOSM.".into()
+                }
+                _ => describe_fetch_error(&fetch_error),
+            };
+
+            model.pending_upload = Some(UploadState::Failed(reason));
+        }
+
+        Msg::ExportPendingChanges => {
+            let time = iso_time(Date::now());
+            download(
+                &format!("surway-edits-{}.osc", time.replace(':', "-")),
+                "application/xml",
+                &model.pending_changes.to_xml(None),
+            );
+        }
+
+        Msg::OsmChangeFilePicked(file) => {
+            let name = file.name();
+            orders.perform_cmd(async move {
+                let result = JsFuture::from(file.text())
+                    .await
+                    .map(|text| text.as_string().unwrap_or_default());
+                Msg::OsmChangeFileRead(name, result)
+            });
+        }
+
+        Msg::OsmChangeFileRead(name, Ok(xml)) => match OsmChange::from_xml(&xml) {
+            Ok(change) => {
+                info!("Imported {} edits from {}.", change.len(), name);
+                model.pending_changes.merge(change);
+                model.pending_upload = None;
+                save_pending_changes(model);
+                show_pending_changes(model, orders);
+            }
+            Err(osm_error) => {
+                model.pending_upload = Some(UploadState::Failed(format!(
+                    "Unable to import {}: {}",
+                    name, osm_error
+                )));
+            }
+        },
+
+        Msg::OsmChangeFileRead(name, Err(error)) => {
+            error!("Reading osmChange file {} failed: {:?}", name, error);
+        }
+
+        Msg::DiscardPendingChanges => {
+            let confirmed = window()
+                .confirm_with_message("Throw away all your edits that haven't been uploaded?")
+                .unwrap_or(false);
+
+            if confirmed {
+                model.pending_changes = OsmChange::default();
+                model.pending_upload = None;
+                save_pending_changes(model);
+
+                // Download the map data again, without our edits
+                reload_osm_data(model, orders);
+            }
+        }
+
+        Msg::AlertRuleToggled(index) => {
+            if let Some(rule) = model.alert_settings.rules.get_mut(index) {
+                rule.enabled = !rule.enabled;
+                save_alert_settings(model);
+            }
+        }
+
+        Msg::DeleteAlertRule(index) => {
+            if index < model.alert_settings.rules.len() {
+                model.alert_settings.rules.remove(index);
+                save_alert_settings(model);
+            }
+        }
+
+        Msg::AlertRuleNameChanged(name) => {
+            model.new_alert_rule_name = name;
+        }
+
+        Msg::AlertRuleChanged(rule) => {
+            model.new_alert_rule = rule;
+            model.new_alert_rule_error = None;
+        }
+
+        Msg::AddAlertRule => {
+            match model
+                .alert_settings
+                .add(&model.new_alert_rule_name, &model.new_alert_rule)
+            {
+                Ok(()) => {
+                    model.new_alert_rule_name.clear();
+                    model.new_alert_rule.clear();
+                    save_alert_settings(model);
+                }
+                Err(error) => model.new_alert_rule_error = Some(error),
+            }
+        }
+
+        Msg::AlertNotificationToggled(notification) => {
+            let notifications = &mut model.alert_settings.notifications;
+
+            if notifications.contains(&notification) {
+                notifications.retain(|n| *n != notification);
+            } else if notification == AlertNotification::System
+                && notification_permission() != Some(NotificationPermission::Granted)
+            {
+                // Turned on once the user allows it
+                orders.perform_cmd(async {
+                    Msg::NotificationPermissionAnswered(request_notification_permission().await)
+                });
+            } else {
+                notifications.push(notification);
+
+                // Let the user hear what it sounds like
+                if notification == AlertNotification::Sound {
+                    create_audio_context(model);
+                    play_alert_sound(model);
+                }
+            }
+
+            save_alert_settings(model);
+        }
+
+        Msg::Tapped => {
+            if model.alert_settings.notifies_by(AlertNotification::Sound)
+                && model.audio_context.is_none()
+            {
+                create_audio_context(model);
+            } else {
+                orders.skip();
+            }
+        }
+
+        Msg::NotificationPermissionAnswered(permission) => {
+            info!("Notification permission: {:?}", permission);
+            let notifications = &mut model.alert_settings.notifications;
+
+            if permission == NotificationPermission::Granted
+                && !notifications.contains(&AlertNotification::System)
+            {
+                notifications.push(AlertNotification::System);
+                save_alert_settings(model);
+            }
+        }
+
+        Msg::AlertRepeatChanged(minutes) => {
+            if let Ok(minutes) = minutes.parse::<f64>() {
+                model.alert_settings.repeat_after = minutes.max(0.0);
+                save_alert_settings(model);
+            }
+        }
+
+        Msg::DismissAlert => {
+            model.alert = None;
+        }
+
+        Msg::NoteFromAlert(index) => {
+            if let Some(alert) = model.alert_history.remove(index) {
+                let note = Note {
+                    id: NoteId::new(),
+                    time: alert.time,
+                    position: alert.position,
+                    text: alert.message,
+                    uploaded: false,
+                    osm_note_id: None,
+                    anonymous: false,
+                };
+
+                // Open the note, to add to what the alert said
+                model.note_id = Some(note.id);
+                model.new_note = note.text.clone();
+                model.notes.push_front(note);
+                model.route = Route::EditNote;
+
+                LocalStorage::insert(NOTE_STORAGE_KEY, &model.notes)
+                    .expect("Unable to save note to LocalStorage");
+                save_alert_history(model);
+                map::render_notes(model);
+            }
+        }
+
+        Msg::ClearAlertHistory => {
+            model.alert_history.clear();
+            save_alert_history(model);
+        }
+
+        Msg::SetMap((
+            map,
+            topology_layer_group,
+            position_layer_group,
+            notes_layer_group,
+            osm_notes_layer_group,
+            track_layer_group,
+        )) => {
+            model.map = Some(map);
+            model.topology_layer_group = Some(topology_layer_group);
+            model.position_layer_group = Some(position_layer_group);
+            model.notes_layer_group = Some(notes_layer_group);
+            model.osm_notes_layer_group = Some(osm_notes_layer_group);
+            model.track_layer_group = Some(track_layer_group);
+            map::set_view(model);
+            map::render_topology_and_position(model);
+            map::render_notes(model);
+            map::render_osm_notes(model);
+            map::render_track(model);
+        }
+
+        Msg::FlipTrackPosition => {
+            model.track_position = !model.track_position;
+            flip_track_position_icon();
+        }
+
+        Msg::FlipWakeLock => {
+            if let Some(sentinel) = &model.wake_lock_sentinel {
+                let _promise = sentinel.release();
+                model.wake_lock_sentinel = None;
+                flip_wake_lock_icon();
+            } else {
+                orders.skip().perform_cmd({
+                    async {
+                        let sentinel: WakeLockSentinel = JsCast::unchecked_into(
+                            wasm_bindgen_futures::JsFuture::from(
+                                wake_lock().request(WakeLockType::Screen),
+                            )
+                            .await
+                            .expect("Unable to get wake lock result."),
+                        );
+
+                        flip_wake_lock_icon();
+                        Msg::KeepWakeLockSentinel(sentinel)
+                    }
+                });
+            }
+        }
+
+        Msg::KeepWakeLockSentinel(sentinel) => {
+            model.wake_lock_sentinel = Some(sentinel);
+        }
+
+        Msg::StartRecording => {
+            model.track.start();
+            save_track(model);
+        }
+
+        Msg::StopRecording => {
+            model.track.stop();
+            save_track(model);
+        }
+
+        Msg::SaveRecordedTrack => {
+            if model.track.recording && !model.track_unsaved {
+                save_track(model);
+            }
+        }
+
+        Msg::ExportTrack => {
+            if let Some(start_time) = model.track.start_time() {
+                let time = iso_time(start_time);
+                let gpx = model.track.to_gpx(&format!("Surway track {}", time));
+                download(
+                    &format!("surway-{}.gpx", time.replace(':', "-")),
+                    "application/gpx+xml",
+                    &gpx,
+                );
+            }
+        }
+
+        Msg::TraceDescriptionChanged(description) => {
+            model.trace_description = description;
+        }
+
+        Msg::TraceTagsChanged(tags) => {
+            model.trace_tags = tags;
+        }
+
+        Msg::TraceVisibilityChanged(visibility) => {
+            if let Some(visibility) = TraceVisibility::parse(&visibility) {
+                model.trace_visibility = visibility;
+            }
+        }
+
+        Msg::UploadTrack => {
+            if model.trace_upload == Some(UploadState::Uploading) {
+                return;
+            }
+
+            if let (Some(access_token), Some(start_time)) =
+                (model.access_token.clone(), model.track.start_time())
+            {
+                let time = iso_time(start_time);
+                let gpx = model.track.to_gpx(&format!("Surway track {}", time));
+                let file_name = format!("surway-{}.gpx", time.replace(':', "-"));
+                let description = model.trace_description.clone();
+                let tags = model.trace_tags.clone();
+                let visibility = model.trace_visibility;
+                model.trace_upload = Some(UploadState::Uploading);
+
+                orders.perform_cmd(async move {
+                    Msg::OsmTracePosted(
+                        send_osm_trace_request(
+                            &access_token,
+                            &gpx,
+                            &file_name,
+                            &description,
+                            &tags,
+                            visibility,
+                        )
+                        .await,
+                    )
+                });
+            }
+        }
+
+        Msg::OsmTracePosted(Ok(trace_id)) => {
+            info!("Uploaded track as GPX trace {}.", trace_id);
+            model.track.trace_id = Some(trace_id);
+            model.trace_upload = None;
+            save_track(model);
+            model.route = Route::Track;
+        }
+
+        Msg::OsmTracePosted(Err(fetch_error)) => {
+            error!("Posting GPX trace failed: {:#?}", fetch_error);
+
+            if is_unauthorized(&fetch_error) {
+                orders.send_msg(Msg::SessionExpired);
+            }
+
+            model.trace_upload = Some(UploadState::Failed(describe_fetch_error(&fetch_error)));
+        }
+
+        Msg::ClearTrack => {
+            let recording = model.track.recording;
+            model.track = Default::default();
+            model.trace_upload = None;
+
+            if recording {
+                model.track.start();
+            }
+
+            save_track(model);
+            map::render_track(model);
+        }
+    }
+}
+
+fn view(model: &Model) -> Node<Msg> {
+    div![
+        div![
+            C!["content"],
+            div![id!["map"],],
+            div![
+                C!["button-row"],
+                a!(
+                    C!["btn btn-primary"],
+                    attrs! {
+                        At::Href => "#notes"
+                    },
+                    "Notes"
+                ),
+                a!(
+                    C![if model.track.recording {
+                        "btn icon-enabled"
+                    } else {
+                        "btn"
+                    }],
+                    attrs! {
+                        At::Href => "#track"
+                    },
+                    "Track"
+                ),
+                a!(
+                    C!["btn"],
+                    attrs! {
+                        At::Href => "#new-poi"
+                    },
+                    "Add POI"
+                ),
+                a!(
+                    C!["btn", IF!(!model.pending_changes.is_empty() => "badge")],
+                    attrs! {
+                        At::Href => "#edits",
+                        At::Custom("data-badge".into()) => model.pending_changes.len()
+                    },
+                    "Edits"
+                ),
+                a!(
+                    C!["btn"],
+                    attrs! {
+                        At::Href => "#settings"
+                    },
+                    "Settings"
+                ),
+                match &model.user {
+                    Some(user) => a!(
+                        C!["btn", IF!(user.unread_messages > 0 => "badge")],
+                        attrs! {
+                            At::Href => "#account",
+                            At::Custom("data-badge".into()) => user.unread_messages
+                        },
+                        view_avatar(user, "avatar-sm"),
+                        format!(" {}", user.name)
+                    ),
+                    None => button!(C!["btn"], "OSM login", ev(Ev::Click, |_| Msg::LogIn)),
+                }
+            ],
+            view_session_expired(model),
+            view_local_osm_file_missing(model),
+            view_placing_poi(model),
+            view_alert(model),
+            view_osm_error(model),
+            view_way(model),
+        ],
+        div![
             C![if model.route != Route::Main {
                 "modal modal-lg active"
             } else {
@@ -396,123 +1736,1297 @@ fn view(model: &Model) -> Node<Msg> {
     ]
 }
 
-fn view_modal(model: &Model) -> Node<Msg> {
-    match model.route {
-        Route::Notes => view_notes(model),
-        Route::EditNote => view_edit_note(model),
-        Route::NewNote => view_edit_note(model),
-        _ => div![],
-    }
-}
+fn view_modal(model: &Model) -> Node<Msg> {
+    match model.route {
+        Route::Notes => view_notes(model),
+        Route::EditNote => view_edit_note(model),
+        Route::NewNote => view_edit_note(model),
+        Route::NewPoi => view_new_poi(model),
+        Route::EditWay(id) => view_edit_way(model, id),
+        Route::Edits => view_edits(model),
+        Route::Settings => view_settings(model),
+        Route::Alerts => view_alerts(model),
+        Route::Track => view_track(model),
+        Route::UploadTrack => view_upload_track(model),
+        Route::OsmNote(id) => view_osm_note(model, id),
+        Route::Account => view_account(model),
+        _ => div![],
+    }
+}
+
+fn view_notes(model: &Model) -> Node<Msg> {
+    div![
+        C!["modal-body"],
+        model.notes.iter().map(|note| {
+            let note_id = note.id;
+            let position = note.position;
+            let time = local_time(note.time);
+
+            div![
+                C!["card-container"],
+                div![
+                    C!["card"],
+                    div![
+                        C!["card-header"],
+                        div![
+                            C!["btn-group  float-right"],
+                            a![
+                                C!["btn"],
+                                attrs! {At::Href => "#"},
+                                img![attrs! {At::Src => "icons/locate.svg"}, C!["icon"]],
+                                ev(Ev::Click, move |_| Msg::Locate(position))
+                            ],
+                            button![
+                                C![if note.uploaded {
+                                    "btn icon-enabled"
+                                } else {
+                                    "btn"
+                                }],
+                                attrs! {At::Disabled => note.uploaded.as_at_value()},
+                                img![attrs! {At::Src => "icons/upload.svg"}, C!["icon"]],
+                                ev(Ev::Click, move |_| Msg::UploadNote(note_id))
+                            ],
+                            button![
+                                C!["btn"],
+                                img![attrs! {At::Src => "icons/pen.svg"}, C!["icon"]],
+                                ev(Ev::Click, move |_| Msg::EditNote(note_id))
+                            ],
+                            button![
+                                C!["btn"],
+                                img![attrs! {At::Src => "icons/trash.svg"}, C!["icon"]],
+                                ev(Ev::Click, move |_| Msg::DeleteNote(note_id))
+                            ],
+                        ],
+                        div![C!["card-subtitle text-gray"], time],
+                    ],
+                    div![
+                        C!["card-body"],
+                        p![note.text.to_string()],
+                        model.outbox.note_state(note_id).map(view_upload_state)
+                    ],
+                ],
+            ]
+        }),
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                "Take a note",
+                ev(Ev::Click, move |_| Msg::NewNote)
+            ],
+            button![
+                C!["btn"],
+                attrs! {At::Disabled => model.notes.iter().all(|note| note.uploaded).as_at_value()},
+                "Upload all",
+                ev(Ev::Click, move |_| Msg::UploadAllNotes)
+            ],
+        ]
+    ]
+}
+
+fn view_upload_state(state: &UploadState) -> Node<Msg> {
+    match state {
+        UploadState::Pending => span![C!["label"], "Waiting to upload"],
+        UploadState::Uploading => span![C!["label label-primary"], "Uploading"],
+        UploadState::Failed(reason) => {
+            span![C!["label label-error"], format!("Failed: {}", reason)]
+        }
+        UploadState::Uploaded(osm_note_id) => a![
+            C!["label label-success"],
+            attrs! {
+                At::Href => format!("https://www.openstreetmap.org/note/{}", osm_note_id),
+                At::Target => "_blank"
+            },
+            format!("Uploaded as note {}", osm_note_id)
+        ],
+    }
+}
+
+fn view_edit_note(model: &Model) -> Node<Msg> {
+    div![
+        C!["modal-body"],
+        textarea![
+            attrs! {At::Value => model.new_note },
+            input_ev(Ev::Input, Msg::NoteChanged)
+        ],
+        div![
+            C!["modal-footer"],
+            div![
+                a![
+                    C!["btn btn-primary"],
+                    attrs! {At::Href => "#"},
+                    "Save",
+                    ev(Ev::Click, |_| Msg::SaveNote)
+                ],
+                a![C!["btn btn-link"], attrs! {At::Href => "#notes"}, "Cancel",]
+            ],
+        ]
+    ]
+}
+
+fn view_new_poi(model: &Model) -> Node<Msg> {
+    let logged_in = model.access_token.is_some();
+
+    let poi = match &model.poi {
+        Some(poi) => poi,
+        None => {
+            return div![
+                C!["modal-body"],
+                model.poi_upload.as_ref().map(view_poi_upload_state),
+                div![
+                    C!["modal-footer"],
+                    button![
+                        C!["btn btn-primary"],
+                        "Add another",
+                        ev(Ev::Click, |_| Msg::NewPoi)
+                    ],
+                    a![C!["btn btn-link"], attrs! {At::Href => "#"}, "Close"]
+                ]
+            ];
+        }
+    };
+
+    let tags = poi.tags();
+    let uploading = model.poi_upload == Some(UploadState::Uploading);
+    let can_upload = logged_in && tags.is_ok() && !poi.comment.trim().is_empty() && !uploading;
+
+    div![
+        C!["modal-body"],
+        if logged_in {
+            empty![]
+        } else {
+            p![C!["text-error"], "Log in to OSM to add points of interest."]
+        },
+        div![
+            C!["form-group"],
+            label![C!["form-label"], "Kind"],
+            select![
+                C!["form-select"],
+                PoiPreset::ALL.iter().map(|preset| option![
+                    attrs! {
+                        At::Value => preset.as_str(),
+                        At::Selected => (*preset == poi.preset).as_at_value()
+                    },
+                    preset.label()
+                ]),
+                input_ev(Ev::Change, Msg::PoiPresetChanged)
+            ],
+            label![C!["form-label"], "Location"],
+            p![format!(
+                "{:.6}, {:.6} ({} m from you)",
+                poi.position.lat,
+                poi.position.lon,
+                poi.position.distance(&model.position).round()
+            )],
+            div![
+                C!["btn-group"],
+                button![
+                    C!["btn btn-sm"],
+                    "Use my position",
+                    ev(Ev::Click, |_| Msg::PoiAtPosition)
+                ],
+                a![
+                    C!["btn btn-sm"],
+                    attrs! {At::Href => "#"},
+                    "Pick on map",
+                    ev(Ev::Click, |_| Msg::PickPoiLocation)
+                ],
+            ],
+            label![C!["form-label"], "Tags (one key=value per line)"],
+            textarea![
+                C!["form-input"],
+                attrs! {At::Value => poi.tags, At::Rows => 6},
+                input_ev(Ev::Input, Msg::PoiTagsChanged)
+            ],
+            match &tags {
+                Err(error) => p![C!["form-input-hint text-error"], error],
+                Ok(_) => empty![],
+            },
+            label![C!["form-label"], "Changeset comment"],
+            input![
+                C!["form-input"],
+                attrs! {At::Value => poi.comment},
+                input_ev(Ev::Input, Msg::PoiCommentChanged)
+            ],
+        ],
+        model.poi_upload.as_ref().map(view_poi_upload_state),
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => (!can_upload).as_at_value()},
+                "Upload",
+                ev(Ev::Click, |_| Msg::UploadPoi)
+            ],
+            button![
+                C!["btn"],
+                attrs! {At::Disabled => (tags.is_err() || uploading).as_at_value()},
+                "Save for later",
+                ev(Ev::Click, |_| Msg::SavePoiForLater)
+            ],
+            a![
+                C!["btn btn-link"],
+                attrs! {At::Href => "#"},
+                "Discard",
+                ev(Ev::Click, |_| Msg::DiscardPoi)
+            ]
+        ]
+    ]
+}
+
+fn view_poi_upload_state(state: &UploadState) -> Node<Msg> {
+    match state {
+        UploadState::Uploaded(node_id) => p![a![
+            C!["label label-success"],
+            attrs! {
+                At::Href => format!("https://www.openstreetmap.org/node/{}", node_id),
+                At::Target => "_blank"
+            },
+            format!("Added as node {}", node_id)
+        ]],
+        _ => p![view_upload_state(state)],
+    }
+}
+
+fn view_edit_way(model: &Model, id: OsmId) -> Node<Msg> {
+    let (edit, way) = match (&model.way_edit, model.osm.way(id)) {
+        (Some(edit), Some(way)) if edit.way_id == id => (edit, way),
+        _ => return div![C!["modal-body"], p!["This way is not loaded."]],
+    };
+
+    if let Some(theirs) = model.way_conflict.as_ref().and_then(|osm| osm.way(id)) {
+        return view_way_conflict(edit, theirs);
+    }
+
+    let logged_in = model.access_token.is_some();
+    let changes = edit.changes();
+    let uploading = model.way_edit_upload == Some(UploadState::Uploading);
+    let has_changes = matches!(&changes, Ok(changes) if !changes.is_empty());
+    let can_upload = logged_in && has_changes && !edit.comment.trim().is_empty() && !uploading;
+
+    div![
+        C!["modal-body"],
+        p![format!(
+            "{}, version {}.",
+            way.label(),
+            way.version
+                .map_or_else(|| "unknown".into(), |version| version.to_string())
+        )],
+        if logged_in {
+            empty![]
+        } else {
+            p![C!["text-error"], "Log in to OSM to edit the way."]
+        },
+        div![
+            C!["form-group"],
+            label![C!["form-label"], "Tags (one key=value per line)"],
+            textarea![
+                C!["form-input"],
+                attrs! {At::Value => edit.tags, At::Rows => 8},
+                input_ev(Ev::Input, Msg::WayTagsChanged)
+            ],
+            match &changes {
+                Ok(changes) => div![
+                    p![C!["form-input-hint"], "Clear a value to delete the tag."],
+                    changes.iter().map(|change| view_tag_change(change, false))
+                ],
+                Err(error) => p![C!["form-input-hint text-error"], error],
+            },
+            label![C!["form-label"], "Changeset comment"],
+            input![
+                C!["form-input"],
+                attrs! {At::Value => edit.comment},
+                input_ev(Ev::Input, Msg::WayEditCommentChanged)
+            ],
+        ],
+        model
+            .way_edit_upload
+            .as_ref()
+            .map(|state| p![view_upload_state(state)]),
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => (!can_upload).as_at_value()},
+                "Upload",
+                ev(Ev::Click, |_| Msg::UploadWayEdit)
+            ],
+            button![
+                C!["btn"],
+                attrs! {At::Disabled => (!has_changes || uploading).as_at_value()},
+                "Save for later",
+                ev(Ev::Click, |_| Msg::SaveWayEditForLater)
+            ],
+            a![
+                C!["btn btn-link"],
+                attrs! {At::Href => "#"},
+                "Cancel",
+                ev(Ev::Click, |_| Msg::DiscardWayEdit)
+            ]
+        ]
+    ]
+}
+
+fn view_way_conflict(edit: &WayEdit, theirs: &OsmWay) -> Node<Msg> {
+    let their_changes = diff_tags(&edit.original, &theirs.tags);
+    let my_changes = edit.changes().unwrap_or_default();
+    let conflicts = conflicting_keys(&my_changes, &their_changes);
+
+    div![
+        C!["modal-body"],
+        div![
+            C!["toast toast-warning"],
+            format!(
+                "Someone else has changed this way since it was downloaded. It is now at version {}.",
+                theirs
+                    .version
+                    .map_or_else(|| "unknown".into(), |version| version.to_string())
+            )
+        ],
+        h6!["Their changes"],
+        if their_changes.is_empty() {
+            p![C!["text-gray"], "No changes to the tags."]
+        } else {
+            div![their_changes
+                .iter()
+                .map(|change| view_tag_change(change, conflicts.contains(&change.key())))]
+        },
+        h6!["Your changes"],
+        my_changes
+            .iter()
+            .map(|change| view_tag_change(change, conflicts.contains(&change.key()))),
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                "Apply mine to their version",
+                ev(Ev::Click, |_| Msg::RebaseWayEdit)
+            ],
+            a![
+                C!["btn btn-link"],
+                attrs! {At::Href => "#"},
+                "Keep theirs",
+                ev(Ev::Click, |_| Msg::DiscardWayEdit)
+            ]
+        ]
+    ]
+}
+
+fn view_tag_change(change: &TagChange, conflict: bool) -> Node<Msg> {
+    div![
+        C![match change {
+            TagChange::Add(_) => "text-success",
+            TagChange::Modify { .. } => "text-warning",
+            TagChange::Delete(_) => "text-error",
+        }],
+        change.to_string(),
+        IF!(conflict => span![C!["label label-error ml-1"], "conflict"]),
+    ]
+}
+
+fn view_edits(model: &Model) -> Node<Msg> {
+    let change = &model.pending_changes;
+    let logged_in = model.access_token.is_some();
+    let uploading = model.pending_upload == Some(UploadState::Uploading);
+    let can_upload =
+        logged_in && !change.is_empty() && !model.pending_comment.trim().is_empty() && !uploading;
+
+    let describe = |action: &str, kind: &str, tags: &[OsmTag]| {
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|tag| format!("{}={}", tag.k, tag.v))
+            .collect();
+        li![format!("{} {}: {}", action, kind, tags.join(", "))]
+    };
+
+    div![
+        C!["modal-body"],
+        if change.is_empty() {
+            p!["No edits are waiting to be uploaded."]
+        } else {
+            ul![
+                change
+                    .create
+                    .nodes
+                    .iter()
+                    .map(|node| describe("New", "node", &node.tags)),
+                change
+                    .create
+                    .ways
+                    .iter()
+                    .map(|way| describe("New", "way", &way.tags)),
+                change.modify.nodes.iter().map(|node| describe(
+                    "Changed",
+                    &format!("node {}", node.id),
+                    &node.tags
+                )),
+                change.modify.ways.iter().map(|way| describe(
+                    "Changed",
+                    &format!("way {}", way.id),
+                    &way.tags
+                )),
+            ]
+        },
+        if logged_in {
+            empty![]
+        } else {
+            p![C!["text-error"], "Log in to OSM to upload your edits."]
+        },
+        div![
+            C!["form-group"],
+            label![C!["form-label"], "Changeset comment"],
+            input![
+                C!["form-input"],
+                attrs! {At::Value => model.pending_comment},
+                input_ev(Ev::Input, Msg::PendingCommentChanged)
+            ],
+            label![C!["form-label"], "Import edits from an osmChange file"],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Type => "file",
+                    At::Accept => ".osc,.xml"
+                },
+                ev(Ev::Change, |event| {
+                    let input: web_sys::HtmlInputElement = event.target()?.unchecked_into();
+                    input.files()?.get(0).map(Msg::OsmChangeFilePicked)
+                })
+            ],
+        ],
+        model.pending_upload.as_ref().map(|state| match state {
+            UploadState::Uploaded(changeset) => p![a![
+                C!["label label-success"],
+                attrs! {
+                    At::Href => format!("https://www.openstreetmap.org/changeset/{}", changeset),
+                    At::Target => "_blank"
+                },
+                format!("Uploaded in changeset {}", changeset)
+            ]],
+            _ => p![view_upload_state(state)],
+        }),
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => (!can_upload).as_at_value()},
+                "Upload",
+                ev(Ev::Click, |_| Msg::UploadPendingChanges)
+            ],
+            button![
+                C!["btn"],
+                attrs! {At::Disabled => change.is_empty().as_at_value()},
+                "Export",
+                ev(Ev::Click, |_| Msg::ExportPendingChanges)
+            ],
+            button![
+                C!["btn btn-link"],
+                attrs! {At::Disabled => (change.is_empty() || uploading).as_at_value()},
+                "Discard",
+                ev(Ev::Click, |_| Msg::DiscardPendingChanges)
+            ],
+        ]
+    ]
+}
+
+fn view_placing_poi(model: &Model) -> Node<Msg> {
+    if model.placing_poi {
+        div![
+            C!["toast toast-primary"],
+            "Tap the map where the point of interest is. ",
+            button![
+                C!["btn btn-sm"],
+                "Cancel",
+                ev(Ev::Click, |_| Msg::CancelPoiPlacement)
+            ],
+        ]
+    } else {
+        div![]
+    }
+}
+
+fn view_osm_note(model: &Model, id: OsmId) -> Node<Msg> {
+    let note = match model.osm_notes.iter().find(|note| note.id == id) {
+        Some(note) => note,
+        None => return div![C!["modal-body"], p!["This note is not loaded."]],
+    };
+
+    let status = match note.status {
+        OsmNoteStatus::Open => "Open",
+        OsmNoteStatus::Closed => "Resolved",
+        OsmNoteStatus::Hidden => "Hidden",
+    };
+
+    let queued_actions = model.outbox.note_actions(id);
+
+    let logged_in = model.access_token.is_some();
+
+    div![
+        C!["modal-body"],
+        p![
+            format!("{} note, created {}. ", status, note.age(Date::now())),
+            a![
+                attrs! {
+                    At::Href => format!("https://www.openstreetmap.org/note/{}", id),
+                    At::Target => "_blank"
+                },
+                "Show on OSM"
+            ]
+        ],
+        note.comments()
+            .iter()
+            .filter(|comment| !comment.text.is_empty())
+            .map(|comment| div![
+                C!["card"],
+                div![
+                    C!["card-header"],
+                    div![
+                        C!["card-subtitle text-gray"],
+                        format!(
+                            "{} {} {}",
+                            comment.user.as_deref().unwrap_or("Anonymous"),
+                            comment.action,
+                            comment.date
+                        )
+                    ]
+                ],
+                div![C!["card-body"], p![comment.text.to_string()]],
+            ]),
+        queued_actions.into_iter().map(|(action, state)| p![
+            C!["text-gray"],
+            format!("{}: {} ", action.kind.path(), action.text),
+            view_upload_state(state)
+        ]),
+        if logged_in {
+            empty![]
+        } else {
+            p![C!["text-error"], "Log in to OSM to comment on this note."]
+        },
+        textarea![
+            attrs! {At::Value => model.osm_note_comment},
+            input_ev(Ev::Input, Msg::OsmNoteCommentChanged)
+        ],
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => (!logged_in || model.osm_note_comment.trim().is_empty()).as_at_value()},
+                "Comment",
+                ev(Ev::Click, |_| Msg::QueueOsmNoteAction(
+                    OsmNoteActionKind::Comment
+                ))
+            ],
+            match note.status {
+                OsmNoteStatus::Open => button![
+                    C!["btn"],
+                    attrs! {At::Disabled => (!logged_in).as_at_value()},
+                    "Resolve",
+                    ev(Ev::Click, |_| Msg::QueueOsmNoteAction(
+                        OsmNoteActionKind::Close
+                    ))
+                ],
+                OsmNoteStatus::Closed => button![
+                    C!["btn"],
+                    attrs! {At::Disabled => (!logged_in).as_at_value()},
+                    "Reopen",
+                    ev(Ev::Click, |_| Msg::QueueOsmNoteAction(
+                        OsmNoteActionKind::Reopen
+                    ))
+                ],
+                OsmNoteStatus::Hidden => empty![],
+            }
+        ]
+    ]
+}
+
+fn view_track(model: &Model) -> Node<Msg> {
+    let track = &model.track;
+    let minutes = (track.duration() / 1000.0 / 60.0).round();
 
-fn view_notes(model: &Model) -> Node<Msg> {
     div![
         C!["modal-body"],
-        model.notes.iter().map(|note| {
-            let note_id = note.id;
-            let position = note.position;
-            let mut time: String = Date::new(&JsValue::from(note.time)).to_string().into();
-
-            if let (Some(start), Some(end)) = (time.find('('), time.find(')')) {
-                time.replace_range(start..=end, "");
-            }
-
-            div![
-                C!["card-container"],
-                div![
-                    C!["card"],
-                    div![
-                        C!["card-header"],
-                        div![
-                            C!["btn-group  float-right"],
-                            a![
-                                C!["btn"],
-                                attrs! {At::Href => "#"},
-                                img![attrs! {At::Src => "icons/locate.svg"}, C!["icon"]],
-                                ev(Ev::Click, move |_| Msg::Locate(position))
-                            ],
-                            button![
-                                C![if note.uploaded {
-                                    "btn icon-enabled"
-                                } else {
-                                    "btn"
-                                }],
-                                img![attrs! {At::Src => "icons/upload.svg"}, C!["icon"]],
-                                ev(Ev::Click, move |_| Msg::UploadNote(note_id))
-                            ],
-                            button![
-                                C!["btn"],
-                                img![attrs! {At::Src => "icons/pen.svg"}, C!["icon"]],
-                                ev(Ev::Click, move |_| Msg::EditNote(note_id))
-                            ],
-                            button![
-                                C!["btn"],
-                                img![attrs! {At::Src => "icons/trash.svg"}, C!["icon"]],
-                                ev(Ev::Click, move |_| Msg::DeleteNote(note_id))
-                            ],
-                        ],
-                        div![C!["card-subtitle text-gray"], time],
-                    ],
-                    div![C!["card-body"], p![note.text.to_string()],],
+        p![if track.recording {
+            "Recording your track."
+        } else {
+            "Not recording."
+        }],
+        p![format!(
+            "{} points in {} segments, {:.2} km in {} min.",
+            track.point_count(),
+            track.segments.len(),
+            track.distance() / 1000.0,
+            minutes
+        )],
+        IF!(model.track_unsaved => p![
+            C!["text-error"],
+            "The track is too long to keep in the browser, and will be lost if Surway is closed. Export it to keep it."
+        ]),
+        track.trace_id.map(|trace_id| p![
+            "Uploaded to OSM as ",
+            match &model.user {
+                Some(user) => a![
+                    attrs! {
+                        At::Href => format!("https://www.openstreetmap.org/user/{}/traces/{}", encode(&user.name), trace_id),
+                        At::Target => "_blank"
+                    },
+                    format!("trace {}", trace_id)
                 ],
-            ]
-        }),
+                None => span![format!("trace {}", trace_id)],
+            },
+            "."
+        ]),
         div![
             C!["modal-footer"],
+            if track.recording {
+                button![
+                    C!["btn btn-primary"],
+                    "Pause recording",
+                    ev(Ev::Click, |_| Msg::StopRecording)
+                ]
+            } else {
+                button![
+                    C!["btn btn-primary"],
+                    "Start recording",
+                    ev(Ev::Click, |_| Msg::StartRecording)
+                ]
+            },
             button![
-                C!["btn btn-primary"],
-                "Take a note",
-                ev(Ev::Click, move |_| Msg::NewNote)
+                C!["btn"],
+                attrs! {At::Disabled => track.is_empty().as_at_value()},
+                "Export GPX",
+                ev(Ev::Click, |_| Msg::ExportTrack)
+            ],
+            a![
+                C![if track.trace_id.is_some() {
+                    "btn icon-enabled"
+                } else {
+                    "btn"
+                }],
+                attrs! {At::Href => "#upload-track"},
+                img![attrs! {At::Src => "icons/upload.svg"}, C!["icon"]],
+                " Upload",
+            ],
+            button![
+                C!["btn btn-link"],
+                attrs! {At::Disabled => track.is_empty().as_at_value()},
+                "Clear",
+                ev(Ev::Click, |_| Msg::ClearTrack)
             ],
         ]
     ]
 }
 
-fn view_edit_note(model: &Model) -> Node<Msg> {
+fn view_upload_track(model: &Model) -> Node<Msg> {
+    let uploading = model.trace_upload == Some(UploadState::Uploading);
+    let can_upload = model.access_token.is_some()
+        && !model.track.is_empty()
+        && !model.trace_description.trim().is_empty()
+        && !uploading;
+
     div![
         C!["modal-body"],
-        textarea![
-            attrs! {At::Value => model.new_note },
-            input_ev(Ev::Input, Msg::NoteChanged)
+        if model.access_token.is_none() {
+            p![C!["text-error"], "Log in to OSM to upload your track."]
+        } else {
+            empty![]
+        },
+        div![
+            C!["form-group"],
+            label![C!["form-label"], "Description"],
+            input![
+                C!["form-input"],
+                attrs! {At::Value => model.trace_description},
+                input_ev(Ev::Input, Msg::TraceDescriptionChanged)
+            ],
+            label![C!["form-label"], "Tags (comma separated)"],
+            input![
+                C!["form-input"],
+                attrs! {At::Value => model.trace_tags},
+                input_ev(Ev::Input, Msg::TraceTagsChanged)
+            ],
+            label![C!["form-label"], "Visibility"],
+            select![
+                C!["form-select"],
+                TraceVisibility::ALL.iter().map(|visibility| option![
+                    attrs! {
+                        At::Value => visibility.as_str(),
+                        At::Selected => (*visibility == model.trace_visibility).as_at_value()
+                    },
+                    visibility.description()
+                ]),
+                input_ev(Ev::Change, Msg::TraceVisibilityChanged)
+            ],
         ],
+        model
+            .trace_upload
+            .as_ref()
+            .map(|state| p![view_upload_state(state)]),
         div![
             C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => (!can_upload).as_at_value()},
+                "Upload",
+                ev(Ev::Click, |_| Msg::UploadTrack)
+            ],
+            a![C!["btn btn-link"], attrs! {At::Href => "#track"}, "Cancel"]
+        ]
+    ]
+}
+
+fn view_settings(model: &Model) -> Node<Msg> {
+    let settings = &model.chunk_store_settings;
+    let profiles = &model.query_profiles;
+    let profile = profiles.active();
+    let filter_errors = profile.tag_filters().1;
+
+    div![
+        C!["modal-body"],
+        view_data_source_settings(model),
+        div![
+            C!["form-group"],
+            h6!["Map features"],
+            label![C!["form-label"], "Profile"],
+            select![
+                C!["form-select"],
+                profiles.all().iter().map(|p| option![
+                    attrs! {
+                        At::Value => p.name,
+                        At::Selected => (p.name == profile.name).as_at_value()
+                    },
+                    &p.name
+                ]),
+                input_ev(Ev::Change, Msg::SelectQueryProfile)
+            ],
+            FeaturePreset::ALL.iter().map(|&preset| label![
+                C!["form-checkbox"],
+                input![
+                    attrs! {
+                        At::Type => "checkbox",
+                        At::Checked => profile.presets.contains(&preset).as_at_value()
+                    },
+                    ev(Ev::Change, move |_| Msg::QueryPresetToggled(preset))
+                ],
+                i![C!["form-icon"]],
+                preset.label()
+            ]),
             div![
-                a![
-                    C!["btn btn-primary"],
-                    attrs! {At::Href => "#"},
+                C![IF!(!filter_errors.is_empty() => "has-error")],
+                label![C!["form-label"], "Custom tag filters (one per line)"],
+                textarea![
+                    C!["form-input"],
+                    attrs! {
+                        At::Placeholder => "waterway\npower=line\nrailway~tram|light_rail",
+                        At::Value => profile.custom_filters.join("\n")
+                    },
+                    input_ev(Ev::Change, Msg::QueryFiltersChanged)
+                ],
+                filter_errors
+                    .iter()
+                    .map(|error| p![C!["form-input-hint"], error]),
+            ],
+            label![C!["form-label"], "Save as a new profile"],
+            div![
+                C!["input-group"],
+                input![
+                    C!["form-input"],
+                    attrs! {
+                        At::Placeholder => "Profile name",
+                        At::Value => model.new_query_profile_name
+                    },
+                    input_ev(Ev::Input, Msg::QueryProfileNameChanged)
+                ],
+                button![
+                    C!["btn input-group-btn"],
+                    attrs! {At::Disabled => model.new_query_profile_name.trim().is_empty().as_at_value()},
                     "Save",
-                    ev(Ev::Click, |_| Msg::SaveNote)
+                    ev(Ev::Click, |_| Msg::SaveQueryProfile)
                 ],
-                a![C!["btn btn-link"], attrs! {At::Href => "#notes"}, "Cancel",]
+            ],
+            button![
+                C!["btn btn-link"],
+                attrs! {At::Disabled => (profiles.all().len() < 2).as_at_value()},
+                format!("Delete the {} profile", profile.name),
+                ev(Ev::Click, |_| Msg::DeleteQueryProfile)
+            ],
+        ],
+        div![
+            C!["form-group"],
+            h6!["Alerts"],
+            p![format!(
+                "{} of {} alert rules are enabled. ",
+                model
+                    .alert_settings
+                    .rules
+                    .iter()
+                    .filter(|rule| rule.enabled)
+                    .count(),
+                model.alert_settings.rules.len()
+            )],
+            a![
+                C!["btn"],
+                attrs! {At::Href => "#alerts"},
+                "Configure alerts"
+            ],
+        ],
+        div![
+            C!["form-group"],
+            h6!["Offline storage"],
+            p![match model.chunk_store_usage {
+                Some((count, size)) => format!(
+                    "{} downloaded chunks are stored, using {:.1} MB.",
+                    count,
+                    size as f64 / 1024.0 / 1024.0
+                ),
+                None => "Checking stored chunks ...".into(),
+            }],
+            label![C!["form-label"], "Storage quota (MB)"],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Type => "number",
+                    At::Min => 1,
+                    At::Value => settings.quota_bytes / 1024 / 1024
+                },
+                input_ev(Ev::Change, Msg::ChunkStoreQuotaChanged)
+            ],
+            label![C!["form-label"], "Keep chunks for (days)"],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Type => "number",
+                    At::Min => 1,
+                    At::Value => settings.expiry_days
+                },
+                input_ev(Ev::Change, Msg::ChunkStoreExpiryChanged)
+            ],
+        ],
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn"],
+                "Clear stored chunks",
+                ev(Ev::Click, |_| Msg::ClearChunkStore)
             ],
         ]
     ]
 }
 
+fn view_alerts(model: &Model) -> Node<Msg> {
+    let settings = &model.alert_settings;
+
+    div![
+        C!["modal-body"],
+        view_alert_history(model),
+        div![
+            C!["form-group"],
+            h6!["Rules"],
+            settings.rules.iter().enumerate().map(|(index, rule)| {
+                let error = rule.parse().err();
+
+                div![
+                    C!["tile tile-centered", IF!(error.is_some() => "has-error")],
+                    div![
+                        C!["tile-content"],
+                        label![
+                            C!["form-switch"],
+                            input![
+                                attrs! {
+                                    At::Type => "checkbox",
+                                    At::Checked => rule.enabled.as_at_value()
+                                },
+                                ev(Ev::Change, move |_| Msg::AlertRuleToggled(index))
+                            ],
+                            i![C!["form-icon"]],
+                            &rule.name
+                        ],
+                        div![C!["tile-subtitle text-gray"], code![&rule.rule]],
+                        error.map(|error| p![C!["form-input-hint"], error]),
+                    ],
+                    div![
+                        C!["tile-action"],
+                        button![
+                            C!["btn btn-link"],
+                            "Delete",
+                            ev(Ev::Click, move |_| Msg::DeleteAlertRule(index))
+                        ]
+                    ]
+                ]
+            }),
+        ],
+        div![
+            C![
+                "form-group",
+                IF!(model.new_alert_rule_error.is_some() => "has-error")
+            ],
+            h6!["New rule"],
+            label![C!["form-label"], "Name"],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Placeholder => "Unpaved road",
+                    At::Value => model.new_alert_rule_name
+                },
+                input_ev(Ev::Input, Msg::AlertRuleNameChanged)
+            ],
+            label![C!["form-label"], "Rule"],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Placeholder => "highway = residential and surface in [gravel, dirt]",
+                    At::Value => model.new_alert_rule
+                },
+                input_ev(Ev::Input, Msg::AlertRuleChanged)
+            ],
+            model
+                .new_alert_rule_error
+                .as_ref()
+                .map(|error| p![C!["form-input-hint"], error]),
+            p![
+                C!["form-input-hint"],
+                "Check tags with key = value, key != value, key in [a, b], has key and missing \
+                 key. Check distances with way_distance, start_distance and end_distance, like \
+                 way_distance > 25 m. Combine with and, or and not. End with for 10 s to alert \
+                 only when the rule has held that long."
+            ],
+            button![C!["btn"], "Add rule", ev(Ev::Click, |_| Msg::AddAlertRule)],
+        ],
+        div![
+            C!["form-group"],
+            h6!["When an alert triggers"],
+            AlertNotification::ALL.iter().map(|&notification| label![
+                C!["form-checkbox"],
+                input![
+                    attrs! {
+                        At::Type => "checkbox",
+                        At::Checked => settings.notifies_by(notification).as_at_value()
+                    },
+                    ev(Ev::Change, move |_| Msg::AlertNotificationToggled(
+                        notification
+                    ))
+                ],
+                i![C!["form-icon"]],
+                notification.label()
+            ]),
+            match notification_permission() {
+                None => p![
+                    C!["form-input-hint"],
+                    "This browser can't show system notifications."
+                ],
+                Some(NotificationPermission::Denied) => p![
+                    C!["form-input-hint"],
+                    "System notifications are blocked for Surway in the browser's site settings."
+                ],
+                Some(_) => empty![],
+            },
+            if settings.notifies_by(AlertNotification::Sound) && model.audio_context.is_none() {
+                p![
+                    C!["form-input-hint"],
+                    "Tap anywhere once to let Surway play sounds."
+                ]
+            } else {
+                empty![]
+            },
+            label![
+                C!["form-label"],
+                "Minutes before alerting again about the same way"
+            ],
+            input![
+                C!["form-input"],
+                attrs! {
+                    At::Type => "number",
+                    At::Min => 0,
+                    At::Value => settings.repeat_after
+                },
+                input_ev(Ev::Change, Msg::AlertRepeatChanged)
+            ],
+        ],
+    ]
+}
+
+/// A time as the browser shows it, without the name of the time zone.
+fn local_time(time: f64) -> String {
+    let mut time: String = Date::new(&JsValue::from(time)).to_string().into();
+
+    if let (Some(start), Some(end)) = (time.find('('), time.find(')')) {
+        time.replace_range(start..=end, "");
+    }
+
+    time
+}
+
+fn view_alert_history(model: &Model) -> Node<Msg> {
+    if model.alert_history.is_empty() {
+        return empty![];
+    }
+
+    div![
+        C!["form-group"],
+        h6!["Recent alerts"],
+        model
+            .alert_history
+            .iter()
+            .enumerate()
+            .map(|(index, alert)| {
+                let time = local_time(alert.time);
+
+                div![
+                    C!["tile tile-centered"],
+                    div![
+                        C!["tile-content"],
+                        div![C!["tile-title"], &alert.message],
+                        div![C!["tile-subtitle text-gray"], time],
+                    ],
+                    div![
+                        C!["tile-action"],
+                        button![
+                            C!["btn btn-link"],
+                            "Take a note",
+                            ev(Ev::Click, move |_| Msg::NoteFromAlert(index))
+                        ]
+                    ]
+                ]
+            }),
+        button![
+            C!["btn btn-link"],
+            "Clear recent alerts",
+            ev(Ev::Click, |_| Msg::ClearAlertHistory)
+        ],
+    ]
+}
+
+fn view_alert(model: &Model) -> Node<Msg> {
+    match &model.alert {
+        Some(alert) => div![
+            C!["toast toast-error"],
+            button![
+                C!["btn btn-clear float-right"],
+                ev(Ev::Click, |_| Msg::DismissAlert)
+            ],
+            &alert.message,
+        ],
+        None => div![],
+    }
+}
+
+fn view_data_source_settings(model: &Model) -> Node<Msg> {
+    let data_source = &model.data_source;
+
+    div![
+        C!["form-group"],
+        h6!["Map data source"],
+        select![
+            C!["form-select"],
+            DataSourceSettings::KINDS.iter().map(|kind| option![
+                attrs! {
+                    At::Value => kind,
+                    At::Selected => (*kind == data_source.kind()).as_at_value()
+                },
+                DataSourceSettings::describe_kind(kind)
+            ]),
+            input_ev(Ev::Change, Msg::DataSourceKindChanged)
+        ],
+        match data_source {
+            DataSourceSettings::LocalFile(_) => div![
+                p![match &model.local_osm_file {
+                    Some(file) => format!("Surveying {}.", file.name),
+                    None => "Pick an OSM XML, JSON or PBF file to survey.".into(),
+                }],
+                view_osm_file_input(),
+            ],
+            _ => div![
+                label![C!["form-label"], "Endpoint"],
+                input![
+                    C!["form-input"],
+                    attrs! {At::Value => data_source.endpoint().unwrap_or_default()},
+                    input_ev(Ev::Change, Msg::DataSourceEndpointChanged)
+                ],
+                label![C!["form-label"], "Format"],
+                select![
+                    C!["form-select"],
+                    DataFormat::ALL.iter().map(|format| option![
+                        attrs! {
+                            At::Value => format.as_str(),
+                            At::Selected => (Some(*format) == data_source.format()).as_at_value()
+                        },
+                        format.as_str().to_uppercase()
+                    ]),
+                    input_ev(Ev::Change, Msg::DataFormatChanged)
+                ],
+            ],
+        },
+    ]
+}
+
+fn view_osm_file_input() -> Node<Msg> {
+    input![
+        C!["form-input"],
+        attrs! {
+            At::Type => "file",
+            At::Accept => ".osm,.osm.xml,.xml,.json,.pbf"
+        },
+        ev(Ev::Change, |event| {
+            let input: web_sys::HtmlInputElement = event.target()?.unchecked_into();
+            input.files()?.get(0).map(Msg::LocalOsmFilePicked)
+        })
+    ]
+}
+
+/// Files can't be read again without asking, so after a reload the user has to pick it again.
+fn view_local_osm_file_missing(model: &Model) -> Node<Msg> {
+    match (&model.data_source, &model.local_osm_file) {
+        (DataSourceSettings::LocalFile(local_file), None) => div![
+            C!["toast"],
+            p![match &local_file.name {
+                Some(name) => format!("Pick {} again to keep surveying it.", name),
+                None => "Pick the OSM file to survey.".into(),
+            }],
+            view_osm_file_input(),
+        ],
+        _ => div![],
+    }
+}
+
 fn route_title(route: Route) -> &'static str {
     match route {
         Route::Notes => "Notes",
         Route::EditNote => "Edit note",
         Route::NewNote => "Take a note",
+        Route::NewPoi => "Add a point of interest",
+        Route::EditWay(_) => "Edit way tags",
+        Route::Edits => "Edits waiting to be uploaded",
+        Route::Settings => "Settings",
+        Route::Alerts => "Alerts",
+        Route::Track => "GPS track",
+        Route::UploadTrack => "Upload track to OSM",
+        Route::OsmNote(_) => "OSM note",
+        Route::Account => "OSM account",
         _ => "Surway",
     }
 }
 
+fn view_session_expired(model: &Model) -> Node<Msg> {
+    if model.session_expired {
+        div![
+            C!["toast toast-warning"],
+            button![
+                C!["btn btn-clear float-right"],
+                ev(Ev::Click, |_| Msg::DismissSessionExpired)
+            ],
+            "Your OSM login has expired. ",
+            button![
+                C!["btn btn-sm"],
+                "Log in again",
+                ev(Ev::Click, |_| Msg::LogIn)
+            ],
+        ]
+    } else {
+        div![]
+    }
+}
+
+fn view_avatar(user: &User, size: &str) -> Node<Msg> {
+    match &user.photo {
+        Some(photo) => figure![
+            C!["avatar", size],
+            img![attrs! {At::Src => photo, At::Alt => user.name}]
+        ],
+        None => figure![
+            C!["avatar", size],
+            attrs! {At::Custom("data-initial".into()) => initials(&user.name)}
+        ],
+    }
+}
+
+fn initials(name: &str) -> String {
+    name.split_whitespace()
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .collect::<String>()
+        .to_uppercase()
+}
+
+fn view_account(model: &Model) -> Node<Msg> {
+    div![
+        C!["modal-body"],
+        match &model.user {
+            Some(user) => div![
+                C!["tile"],
+                div![C!["tile-icon"], view_avatar(user, "avatar-xl")],
+                div![
+                    C!["tile-content"],
+                    p![
+                        C!["tile-title h5"],
+                        a![
+                            attrs! {
+                                At::Href => format!("https://www.openstreetmap.org/user/{}", encode(&user.name)),
+                                At::Target => "_blank"
+                            },
+                            &user.name
+                        ]
+                    ],
+                    p![
+                        C!["tile-subtitle"],
+                        format!(
+                            "Mapper since {}.",
+                            user.account_created.get(..10).unwrap_or("an unknown date")
+                        )
+                    ],
+                    p![format!(
+                        "{} changeset{}.",
+                        user.changeset_count,
+                        if user.changeset_count == 1 { "" } else { "s" }
+                    )],
+                    p![a![
+                        attrs! {
+                            At::Href => "https://www.openstreetmap.org/messages/inbox",
+                            At::Target => "_blank"
+                        },
+                        format!(
+                            "{} unread message{}.",
+                            user.unread_messages,
+                            if user.unread_messages == 1 { "" } else { "s" }
+                        )
+                    ]],
+                ]
+            ],
+            None => p!["Not logged in to OSM."],
+        },
+        div![
+            C!["modal-footer"],
+            button![
+                C!["btn btn-primary"],
+                attrs! {At::Disabled => model.access_token.is_none().as_at_value()},
+                "Log out",
+                ev(Ev::Click, |_| Msg::LogOut)
+            ],
+        ]
+    ]
+}
+
+fn view_osm_error(model: &Model) -> Node<Msg> {
+    match &model.osm_error {
+        Some(osm_error) => div![
+            C!["toast toast-error"],
+            button![
+                C!["btn btn-clear float-right"],
+                ev(Ev::Click, |_| Msg::DismissOsmError)
+            ],
+            osm_error.to_string(),
+        ],
+        None => div![],
+    }
+}
+
 fn view_way(model: &Model) -> Node<Msg> {
     match (
-        model.nearest_way_id.clone(),
+        model
+            .nearest_way_id
+            .and_then(|way_id| model.osm.way(way_id)),
         model.start_distance,
         model.end_distance,
         model.way_distance,
     ) {
-        (Some(way_id), Some(start_distance), Some(end_distance), Some(way_distance)) => {
-            let way = model
-                .osm
-                .ways
-                .iter()
-                .find(|w| w.id == way_id)
-                .expect("No longer have the way with the expected id.");
+        (Some(way), Some(start_distance), Some(end_distance), Some(way_distance)) => {
             div![
                 C!["way-info"],
                 div![
@@ -520,8 +3034,35 @@ fn view_way(model: &Model) -> Node<Msg> {
                     way.tags.iter().map(|tag| div![
                         img![attrs! {At::Src => "icons/tag.svg"}, C!["icon"]],
                         format!(" {} = {}", tag.k, tag.v),
+                    ]),
+                    a![
+                        C!["btn btn-sm"],
+                        attrs! {At::Href => format!("#edit-way/{}", way.id)},
+                        "Edit tags"
+                    ]
+                ],
+                div![
+                    C!["flex-list"],
+                    way.routes(&model.osm).iter().map(|route| div![
+                        img![attrs! {At::Src => "icons/route.svg"}, C!["icon"]],
+                        match route.role_of(OsmMemberType::Way, way.id) {
+                            Some(role) if !role.is_empty() =>
+                                format!(" {} ({})", route.label(), role),
+                            _ => format!(" {}", route.label()),
+                        },
                     ])
                 ],
+                div![
+                    C!["flex-list"],
+                    model
+                        .find_nearby_ways(NEARBY_WAY_RADIUS)
+                        .into_iter()
+                        .filter(|(nearby, _)| nearby.id != way.id)
+                        .map(|(nearby, distance)| div![
+                            img![attrs! {At::Src => "icons/ruler-green.svg"}, C!["icon"]],
+                            format!(" {}: {} m", nearby.label(), distance.round()),
+                        ])
+                ],
                 div![
                     C!["flex-list"],
                     div![
@@ -541,19 +3082,14 @@ fn view_way(model: &Model) -> Node<Msg> {
         }
         _ => div![],
     }
-}
-
-async fn send_osm_map_request(bbox: &BoundingBox) -> fetch::Result<String> {
-    let url = format!(
-        "https://overpass-api.de/api/interpreter?data=[bbox];way[highway];(._;>;);out;&bbox={},{},{},{}",
-        bbox.lower_left.lon, bbox.lower_left.lat, bbox.upper_right.lon, bbox.upper_right.lat
-    );
+}
 
+async fn send_osm_map_request(url: String) -> fetch::Result<String> {
     info!("Fetching query {}", url);
 
     let response = Request::new(url).fetch().await?;
     let status = response.status();
-    let body = response.text().await.expect("Unable to get response text");
+    let body = response.text().await?;
 
     if status.category == fetch::StatusCategory::Success {
         Ok(body)
@@ -562,7 +3098,50 @@ async fn send_osm_map_request(bbox: &BoundingBox) -> fetch::Result<String> {
     }
 }
 
-async fn send_osm_note_request(note: Note) -> fetch::Result<NoteId> {
+async fn send_osm_notes_request(bbox: &BoundingBox) -> fetch::Result<String> {
+    // Include notes closed during the last week, so recent fixes show up too
+    let url = format!(
+        "https://api.openstreetmap.org/api/0.6/notes?bbox={},{},{},{}&closed=7",
+        bbox.lower_left.lon, bbox.lower_left.lat, bbox.upper_right.lon, bbox.upper_right.lat
+    );
+
+    info!("Fetching notes {}", url);
+
+    Request::new(url)
+        .fetch()
+        .await?
+        .check_status()?
+        .text()
+        .await
+}
+
+async fn send_osm_note_action_request(
+    access_token: &str,
+    action: &OsmNoteAction,
+) -> fetch::Result<String> {
+    let mut url = format!(
+        "https://api.openstreetmap.org/api/0.6/notes/{}/{}",
+        action.note_id,
+        action.kind.path()
+    );
+
+    if !action.text.is_empty() {
+        url = format!("{}?text={}", url, encode(&action.text));
+    }
+
+    info!("Posting note action {}", url);
+
+    Request::new(url)
+        .method(Method::Post)
+        .header(Header::authorization(format!("Bearer {}", access_token)))
+        .fetch()
+        .await?
+        .check_status()?
+        .text()
+        .await
+}
+
+async fn send_osm_note_request(note: Note, access_token: Option<String>) -> fetch::Result<String> {
     let url = format!(
         "https://api.openstreetmap.org/api/0.6/notes?lat={}&lon={}&text={}",
         note.position.lat,
@@ -572,79 +3151,228 @@ async fn send_osm_note_request(note: Note) -> fetch::Result<NoteId> {
 
     info!("Posting note {}", url);
 
-    let status = Request::new(url)
+    let mut request = Request::new(url).method(Method::Post);
+
+    // Without a token, the note is posted anonymously
+    if let Some(access_token) = access_token {
+        request = request.header(Header::authorization(format!("Bearer {}", access_token)));
+    }
+
+    request.fetch().await?.check_status()?.text().await
+}
+
+async fn send_osm_trace_request(
+    access_token: &str,
+    gpx: &str,
+    file_name: &str,
+    description: &str,
+    tags: &str,
+    visibility: TraceVisibility,
+) -> fetch::Result<u64> {
+    let url = "https://api.openstreetmap.org/api/0.6/gpx/create";
+
+    let form = FormData::new().map_err(FetchError::RequestError)?;
+    form.append_with_blob_and_filename("file", &blob(gpx, "application/gpx+xml"), file_name)
+        .map_err(FetchError::RequestError)?;
+    form.append_with_str("description", description)
+        .map_err(FetchError::RequestError)?;
+    form.append_with_str("tags", tags)
+        .map_err(FetchError::RequestError)?;
+    form.append_with_str("visibility", visibility.as_str())
+        .map_err(FetchError::RequestError)?;
+
+    info!("Posting GPX trace {}", file_name);
+
+    let response = Request::new(url)
         .method(Method::Post)
+        .header(Header::authorization(format!("Bearer {}", access_token)))
+        .body(form.into())
         .fetch()
         .await?
-        .status();
+        .check_status()?;
 
-    if status.category == fetch::StatusCategory::Success {
-        Ok(note.id)
-    } else {
-        Err(FetchError::StatusError(status))
-    }
+    let body = response.text().await?;
+
+    body.trim()
+        .parse()
+        .map_err(|_| FetchError::PromiseError(format!("Unexpected trace id: {}", body).into()))
 }
 
-async fn send_osm_token_request(code: &str) -> fetch::Result<String> {
-    let url = "https://www.openstreetmap.org/oauth2/token";
+/// Opens a changeset, uploads the change to it, and closes it again.
+async fn upload_osm_change(
+    access_token: &str,
+    comment: &str,
+    change: &OsmChange,
+) -> fetch::Result<(OsmId, DiffResult)> {
+    let tags = [
+        OsmTag {
+            k: "created_by".into(),
+            v: "Surway".into(),
+        },
+        OsmTag {
+            k: "comment".into(),
+            v: comment.into(),
+        },
+    ];
+
+    let changeset = send_osm_changeset_create_request(access_token, &changeset_xml(&tags)).await?;
+    let uploaded = send_osm_change_request(access_token, changeset, change).await;
+
+    // Close the changeset even when the upload failed, instead of leaving it open for an hour
+    let closed = send_osm_changeset_close_request(access_token, changeset).await;
+    let diff = uploaded?;
+    closed?;
+
+    let diff = DiffResult::from_xml(&diff)
+        .map_err(|osm_error| FetchError::PromiseError(osm_error.to_string().into()))?;
+
+    Ok((changeset, diff))
+}
+
+async fn send_osm_changeset_create_request(
+    access_token: &str,
+    changeset: &str,
+) -> fetch::Result<OsmId> {
+    let url = "https://api.openstreetmap.org/api/0.6/changeset/create";
+
+    info!("Creating changeset");
+
+    let response = Request::new(url)
+        .method(Method::Put)
+        .header(Header::authorization(format!("Bearer {}", access_token)))
+        .text(changeset)
+        .header(Header::content_type("text/xml"))
+        .fetch()
+        .await?;
+
+    let body = check_changeset_status(response).await?.text().await?;
+
+    body.trim()
+        .parse()
+        .map_err(|_| FetchError::PromiseError(format!("Unexpected changeset id: {}", body).into()))
+}
+
+async fn send_osm_change_request(
+    access_token: &str,
+    changeset: OsmId,
+    change: &OsmChange,
+) -> fetch::Result<String> {
+    let url = format!(
+        "https://api.openstreetmap.org/api/0.6/changeset/{}/upload",
+        changeset
+    );
+
+    info!("Uploading to changeset {}", changeset);
 
     let response = Request::new(url)
         .method(Method::Post)
-        .text(format!(
-            "grant_type=authorization_code&redirect_uri=http://127.0.0.1:8088/callback&code={}",
-            code
-        ))
-        .header(Header::content_type("application/x-www-form-urlencoded"))
-        .header(Header::authorization(format!("Basic {}", oauth2_auth())))
+        .header(Header::authorization(format!("Bearer {}", access_token)))
+        .text(change.to_xml(Some(changeset)))
+        .header(Header::content_type("text/xml"))
         .fetch()
         .await?;
 
-    let status = response.status();
-    let body: OAuth2Response = response.json().await.expect("Unable to get response text");
+    check_changeset_status(response).await?.text().await
+}
 
-    if status.category == fetch::StatusCategory::Success {
-        info!("Response: {:?}", body);
-        Ok(body.access_token)
-    } else {
-        Err(FetchError::StatusError(status))
-    }
+async fn send_osm_changeset_close_request(
+    access_token: &str,
+    changeset: OsmId,
+) -> fetch::Result<()> {
+    let url = format!(
+        "https://api.openstreetmap.org/api/0.6/changeset/{}/close",
+        changeset
+    );
+
+    info!("Closing changeset {}", changeset);
+
+    Request::new(url)
+        .method(Method::Put)
+        .header(Header::authorization(format!("Bearer {}", access_token)))
+        .fetch()
+        .await?
+        .check_status()?;
+
+    Ok(())
+}
+
+async fn send_osm_way_request(way_id: OsmId) -> fetch::Result<String> {
+    let url = format!("https://api.openstreetmap.org/api/0.6/way/{}/full", way_id);
+
+    info!("Fetching way {}", url);
+
+    Request::new(url)
+        .fetch()
+        .await?
+        .check_status()?
+        .text()
+        .await
+}
+
+async fn send_osm_token_request(code: &str, login: &PendingLogin) -> fetch::Result<String> {
+    let url = "https://www.openstreetmap.org/oauth2/token";
+
+    let response: OAuth2Response = Request::new(url)
+        .method(Method::Post)
+        .text(login.token_request_body(code, &oauth2_client(), &oauth2_callback()))
+        .header(Header::content_type("application/x-www-form-urlencoded"))
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await?;
+
+    // Not logging the response, as it holds the access token
+    Ok(response.access_token)
 }
 
 async fn send_osm_user_request(access_token: &str) -> fetch::Result<User> {
     let url = "https://www.openstreetmap.org/api/0.6/user/details.json";
 
-    let response = Request::new(url)
+    let response: UserResponse = Request::new(url)
         .header(Header::authorization(format!("Bearer {}", access_token)))
         .fetch()
+        .await?
+        .check_status()?
+        .json()
         .await?;
 
-    let status = response.status();
-    let response: UserResponse = response.json().await.expect("Unable to get user JSON");
+    info!("Response: {:?}", response);
 
-    if status.category == fetch::StatusCategory::Success {
-        info!("Response: {:?}", response);
-        let user = User {
-            name: response.user.display_name,
-            photo: "".into(),
-        };
-        Ok(user)
-    } else {
-        Err(FetchError::StatusError(status))
-    }
+    Ok(response.user.into())
+}
+
+async fn send_osm_revoke_request(access_token: &str) -> fetch::Result<()> {
+    let url = "https://www.openstreetmap.org/oauth2/revoke";
+
+    Request::new(url)
+        .method(Method::Post)
+        .text(format!(
+            "token={}&client_id={}",
+            encode(access_token),
+            encode(&oauth2_client())
+        ))
+        .header(Header::content_type("application/x-www-form-urlencoded"))
+        .fetch()
+        .await?
+        .check_status()?;
+
+    Ok(())
 }
 
 fn update_position(position: Coord, model: &mut Model, orders: &mut impl Orders<Msg>) {
     if model.nearest_way_id.is_some() && position == model.position {
         info!("Position unchanged.");
+        check_alerts(model);
         return;
     }
 
     model.position = position;
     let nearest_way = model.find_nearest_way();
-    let nearest_way_id = nearest_way.map(|w| w.id.clone());
+    let nearest_way_id = nearest_way.map(|w| w.id);
 
-    let start_position = nearest_way.map(|w| w.start(&model.osm)).flatten();
-    let end_position = nearest_way.map(|w| w.end(&model.osm)).flatten();
+    let start_position = nearest_way.and_then(|w| w.start(&model.osm));
+    let end_position = nearest_way.and_then(|w| w.end(&model.osm));
 
     let start_distance = start_position.map(|p| p.distance(&model.position).round());
     let end_distance = end_position.map(|p| p.distance(&model.position).round());
@@ -654,6 +3382,7 @@ fn update_position(position: Coord, model: &mut Model, orders: &mut impl Orders<
     model.start_distance = start_distance;
     model.end_distance = end_distance;
     model.way_distance = way_distance;
+    check_alerts(model);
 
     if model.track_position {
         pan_to_position(model, position);
@@ -670,6 +3399,442 @@ fn update_position(position: Coord, model: &mut Model, orders: &mut impl Orders<
     orders.after_next_render(|_| Msg::InvalidateMapSize);
 }
 
+/// Checks the alert rules against the way nearest to us, and notifies about those that trigger.
+fn check_alerts(model: &mut Model) {
+    let (way, way_distance) = match (model.nearest_way_id, model.way_distance) {
+        (Some(way_id), Some(way_distance)) => match model.osm.way(way_id) {
+            Some(way) => (way, way_distance),
+            None => return,
+        },
+        // Until there is map data, there is nothing to check
+        _ => return,
+    };
+
+    let context = AlertContext {
+        position: model.position,
+        way,
+        way_distance,
+        start_distance: model.start_distance,
+        end_distance: model.end_distance,
+    };
+
+    let alerts = model
+        .alert_monitor
+        .check(&model.alert_settings, &context, Date::now());
+
+    for alert in alerts {
+        notify(model, alert);
+    }
+}
+
+fn notify(model: &mut Model, alert: Alert) {
+    info!("Alert: {}", alert.message);
+
+    if model
+        .alert_settings
+        .notifies_by(AlertNotification::Vibration)
+    {
+        window().navigator().vibrate_with_duration(400);
+    }
+
+    if model.alert_settings.notifies_by(AlertNotification::Sound) {
+        play_alert_sound(model);
+    }
+
+    if model.alert_settings.notifies_by(AlertNotification::System) {
+        show_system_notification(&alert);
+    }
+
+    model.alert_history.push_front(alert.clone());
+    model.alert_history.truncate(ALERT_HISTORY_LENGTH);
+    save_alert_history(model);
+
+    if model.alert_settings.notifies_by(AlertNotification::Visual) {
+        model.alert = Some(alert);
+    }
+}
+
+/// The permission to show notifications, or `None` if the browser can't show them.
+fn notification_permission() -> Option<NotificationPermission> {
+    let is_supported =
+        js_sys::Reflect::has(&window(), &JsValue::from_str("Notification")).unwrap_or(false);
+
+    is_supported.then(Notification::permission)
+}
+
+async fn request_notification_permission() -> NotificationPermission {
+    if notification_permission().is_none() {
+        return NotificationPermission::Denied;
+    }
+
+    match Notification::request_permission() {
+        Ok(promise) => wasm_bindgen_futures::JsFuture::from(promise)
+            .await
+            .ok()
+            .and_then(|permission| NotificationPermission::from_js_value(&permission))
+            .unwrap_or(NotificationPermission::Denied),
+        Err(e) => {
+            error!(
+                "Unable to ask for permission to show notifications: {:?}",
+                e
+            );
+            NotificationPermission::Denied
+        }
+    }
+}
+
+fn is_service_worker_supported() -> bool {
+    js_sys::Reflect::has(&window().navigator(), &JsValue::from_str("serviceWorker"))
+        .unwrap_or(false)
+}
+
+fn register_service_worker() {
+    if !is_service_worker_supported() {
+        warn!("Service workers are not supported.");
+        return;
+    }
+
+    let promise = window().navigator().service_worker().register("/sw.js");
+
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(e) = wasm_bindgen_futures::JsFuture::from(promise).await {
+            error!("Unable to register service worker: {:?}", e);
+        }
+    });
+}
+
+fn show_system_notification(alert: &Alert) {
+    if notification_permission() != Some(NotificationPermission::Granted) {
+        warn!("Not allowed to show notifications.");
+        return;
+    }
+
+    let mut options = NotificationOptions::new();
+
+    // A newer notification about the same rule and way replaces the older one
+    options
+        .body(&alert.message)
+        .tag(&format!("{}/{}", alert.rule, alert.way_id))
+        .icon("/android-chrome-192x192.png");
+
+    if !is_service_worker_supported() {
+        if let Err(e) = Notification::new_with_options("Surway", &options) {
+            error!("Unable to show notification: {:?}", e);
+        }
+        return;
+    }
+
+    // Some browsers (like Chrome on Android) refuse to construct notifications, and only show them
+    // through a service worker
+    let registration = window().navigator().service_worker().get_registration();
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let result = match wasm_bindgen_futures::JsFuture::from(registration).await {
+            Ok(registration) if !registration.is_undefined() => {
+                let registration: ServiceWorkerRegistration = registration.unchecked_into();
+                registration
+                    .show_notification_with_options("Surway", &options)
+                    .map(|_| ())
+            }
+            // Not registered (yet), so try without it
+            Ok(_) => Notification::new_with_options("Surway", &options).map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            error!("Unable to show notification: {:?}", e);
+        }
+    });
+}
+
+/// Must be called from a user gesture, or the context starts suspended and stays silent.
+fn create_audio_context(model: &mut Model) {
+    if model.audio_context.is_none() {
+        model.audio_context = AudioContext::new()
+            .map_err(|e| error!("Unable to play sounds: {:?}", e))
+            .ok();
+    }
+}
+
+fn play_alert_sound(model: &Model) {
+    if let Some(context) = &model.audio_context {
+        let beep = || -> Result<(), JsValue> {
+            let _promise = context.resume()?;
+            let oscillator = context.create_oscillator()?;
+            oscillator.frequency().set_value(880.0);
+            oscillator.connect_with_audio_node(&context.destination())?;
+            oscillator.start()?;
+            oscillator.stop_with_when(context.current_time() + 0.3)
+        };
+
+        if let Err(e) = beep() {
+            error!("Unable to play alert sound: {:?}", e);
+        }
+    }
+}
+
+fn load_osm_document(mut osm: OsmDocument, model: &mut Model, orders: &mut impl Orders<Msg>) {
+    if !model.data_source.source().applies_query() {
+        let profile = model.query_profiles.active();
+        osm.retain_ways(|way| profile.way_color(&way.tags).is_some());
+    }
+
+    model.osm.merge(osm);
+
+    // Keep showing our edits over the downloaded data, until they are uploaded
+    model.osm.merge(model.pending_changes.to_document());
+
+    // A whole local file is all there is, so keep all of it
+    if !model.has_whole_osm_file() {
+        model.osm.evict(&model.position, model.osm_eviction_radius);
+    }
+
+    // The new data may have a way closer than the one we had
+    refresh_osm_data(model, orders);
+}
+
+/// Must be called after changing `model.osm`, since the nearest way may have changed, or even be
+/// gone.
+fn refresh_osm_data(model: &mut Model, orders: &mut impl Orders<Msg>) {
+    model.segment_index = SegmentIndex::new(&model.osm);
+    model.nearest_way_id = None;
+    update_position(model.position, model, orders);
+    map::render_topology_and_position(model);
+}
+
+/// Replaces the OSM data with what the active map profile asks for.
+fn reload_osm_data(model: &mut Model, orders: &mut impl Orders<Msg>) {
+    save_query_profiles(model);
+
+    model.osm = OsmDocument::new();
+    model.segment_index = SegmentIndex::new(&model.osm);
+    model.nearest_way_id = None;
+    model.stored_chunks_loaded = false;
+    map::render_topology_and_position(model);
+
+    if model.is_using_local_file() {
+        load_local_osm_file(model, orders);
+    } else if model.osm_chunk_position.is_some() {
+        orders.send_msg(Msg::DownloadOsmChunk);
+    }
+}
+
+fn load_local_osm_file(model: &mut Model, orders: &mut impl Orders<Msg>) {
+    let bbox = model.position.bbox(model.osm_chunk_radius);
+
+    let parsed = match &model.local_osm_file {
+        Some(LocalOsmFile {
+            name,
+            data: LocalOsmData::Text(text),
+        }) => (name.clone(), OsmDocument::parse_extract(text)),
+        Some(LocalOsmFile {
+            name,
+            data: LocalOsmData::Pbf(bytes),
+        }) => (name.clone(), OsmDocument::from_pbf(bytes, &bbox)),
+        None => return,
+    };
+
+    match parsed {
+        (name, Ok(osm)) => {
+            info!("Loaded OSM file {}.", name);
+            model.osm_error = None;
+            load_osm_document(osm, model, orders);
+        }
+        (name, Err(osm_error)) => {
+            error!("Loading OSM file {} failed: {}", name, osm_error);
+            model.osm_error = Some(osm_error);
+        }
+    }
+}
+
+fn save_data_source(model: &Model) {
+    LocalStorage::insert(DATA_SOURCE_STORAGE_KEY, &model.data_source)
+        .expect("Unable to save data source to LocalStorage");
+}
+
+fn save_alert_settings(model: &Model) {
+    LocalStorage::insert(ALERT_SETTINGS_STORAGE_KEY, &model.alert_settings)
+        .expect("Unable to save alert settings to LocalStorage");
+}
+
+fn save_alert_history(model: &Model) {
+    LocalStorage::insert(ALERT_HISTORY_STORAGE_KEY, &model.alert_history)
+        .expect("Unable to save alert history to LocalStorage");
+}
+
+fn save_query_profiles(model: &Model) {
+    LocalStorage::insert(QUERY_PROFILES_STORAGE_KEY, &model.query_profiles)
+        .expect("Unable to save map profiles to LocalStorage");
+}
+
+fn save_chunk_store_settings(model: &Model) {
+    LocalStorage::insert(
+        CHUNK_STORE_SETTINGS_STORAGE_KEY,
+        &model.chunk_store_settings,
+    )
+    .expect("Unable to save chunk store settings to LocalStorage");
+}
+
+/// Asks before posting notes anonymously, and remembers the answer on the notes.
+fn confirm_anonymous_upload(model: &mut Model, selected: impl Fn(&Note) -> bool) -> bool {
+    if model.access_token.is_some() {
+        return true;
+    }
+
+    let confirmed = window()
+        .confirm_with_message(
+            "You are not logged in to OSM, so your notes will be posted anonymously. Continue?",
+        )
+        .unwrap_or(false);
+
+    if confirmed {
+        for note in model.notes.iter_mut().filter(|note| selected(note)) {
+            note.anonymous = true;
+        }
+
+        LocalStorage::insert(NOTE_STORAGE_KEY, &model.notes)
+            .expect("Unable to save note to LocalStorage");
+    }
+
+    confirmed
+}
+
+/// Starts adding a POI at the current position.
+fn new_poi(model: &mut Model) {
+    model.poi = Some(PoiDraft::new(PoiPreset::Bench, model.position));
+    model.poi_upload = None;
+    map::render_position(model);
+}
+
+/// Gets the latest version of the edited way from OSM, to put the edited tags on. Our copy may be
+/// out of date, or miss the nodes outside the area we have loaded.
+fn fetch_latest_way(model: &mut Model, orders: &mut impl Orders<Msg>, target: WayEditTarget) {
+    if let Some(edit) = &model.way_edit {
+        let way_id = edit.way_id;
+        model.way_edit_upload = Some(UploadState::Uploading);
+
+        orders.perform_cmd(async move {
+            Msg::LatestWayFetched(target, send_osm_way_request(way_id).await)
+        });
+    }
+}
+
+fn save_pending_changes(model: &Model) {
+    LocalStorage::insert(
+        PENDING_CHANGES_STORAGE_KEY,
+        &model.pending_changes.to_xml(None),
+    )
+    .expect("Unable to save edits to LocalStorage");
+}
+
+fn show_pending_changes(model: &mut Model, orders: &mut impl Orders<Msg>) {
+    model.osm.merge(model.pending_changes.to_document());
+    refresh_osm_data(model, orders);
+}
+
+fn go_to(hash: &str) {
+    window()
+        .location()
+        .set_hash(hash)
+        .expect("Unable to set location hash");
+}
+
+fn end_session(model: &mut Model) {
+    model.access_token = None;
+    model.user = None;
+    LocalStorage::remove(ACCESS_TOKEN_STORAGE_KEY)
+        .expect("Unable to remove access token from LocalStorage");
+}
+
+fn is_unauthorized(fetch_error: &FetchError) -> bool {
+    matches!(fetch_error, FetchError::StatusError(status) if status.code == 401)
+}
+
+/// OSM refuses changesets with 403 Forbidden for several reasons, like a blocked user or
+/// contributor terms not yet accepted, and tells which in the response.
+async fn check_changeset_status(response: fetch::Response) -> fetch::Result<fetch::Response> {
+    let status = response.status();
+
+    if status.code == 403 {
+        let reason = response.text().await.unwrap_or_default();
+        return Err(FetchError::PromiseError(
+            format!("{} {}: {}", status.code, status.text, reason.trim()).into(),
+        ));
+    }
+
+    response.check_status()
+}
+
+fn save_outbox(model: &Model) {
+    LocalStorage::insert(OUTBOX_STORAGE_KEY, &model.outbox)
+        .expect("Unable to save outbox to LocalStorage");
+}
+
+/// Failures caused by the request itself won't go away by sending it again.
+fn is_transient(fetch_error: &FetchError) -> bool {
+    match fetch_error {
+        FetchError::StatusError(status) => {
+            status.code == 408 || status.code == 429 || status.code >= 500
+        }
+        _ => true,
+    }
+}
+
+fn describe_fetch_error(fetch_error: &FetchError) -> String {
+    match fetch_error {
+        FetchError::StatusError(status) => format!("{} {}", status.code, status.text),
+        FetchError::NetworkError(_) => "No connection".into(),
+        FetchError::PromiseError(reason) if reason.is_string() => {
+            reason.as_string().unwrap_or_default()
+        }
+        _ => format!("{:?}", fetch_error),
+    }
+}
+
+/// Saves the track while recording. The whole track is written each time, so this is done only
+/// every now and then, and not at all once the track no longer fits.
+fn save_recorded_track(model: &mut Model) {
+    if !model.track_unsaved && Date::now() - model.track_saved_at >= TRACK_SAVE_INTERVAL {
+        save_track(model);
+    }
+}
+
+fn save_track(model: &mut Model) {
+    model.track_saved_at = Date::now();
+
+    match LocalStorage::insert(TRACK_STORAGE_KEY, &model.track) {
+        Ok(()) => model.track_unsaved = false,
+        Err(e) => {
+            warn!("Unable to save track to LocalStorage: {:?}", e);
+            model.track_unsaved = true;
+        }
+    }
+}
+
+fn blob(content: &str, mime_type: &str) -> Blob {
+    let parts = js_sys::Array::of1(&JsValue::from(content));
+    Blob::new_with_str_sequence_and_options(&parts, BlobPropertyBag::new().type_(mime_type))
+        .expect("Unable to create blob")
+}
+
+fn download(file_name: &str, mime_type: &str, content: &str) {
+    let blob = blob(content, mime_type);
+
+    let url = web_sys::Url::create_object_url_with_blob(&blob).expect("Unable to create blob URL");
+
+    let link = document()
+        .create_element("a")
+        .expect("Unable to create link")
+        .dyn_into::<HtmlAnchorElement>()
+        .expect("Unable to cast to HtmlAnchorElement");
+
+    link.set_href(&url);
+    link.set_download(file_name);
+    link.click();
+
+    web_sys::Url::revoke_object_url(&url).expect("Unable to revoke blob URL");
+}
+
 fn pan_to_position(model: &mut Model, position: Coord) {
     map::pan_to_position(model, position);
 }
@@ -684,9 +3849,8 @@ fn init_geolocation(orders: &mut impl Orders<Msg>) {
 
     let geo_callback = move |position: JsValue| {
         let pos: GeolocationPosition = position.into();
-        let coords = pos.coords();
 
-        app.update(msg_mapper(Msg::Position(coords.into())));
+        app.update(msg_mapper(Msg::Position(pos.into())));
     };
 
     let geo_callback_function = Closure::wrap(Box::new(geo_callback) as Box<dyn FnMut(JsValue)>);
@@ -764,11 +3928,9 @@ cfg_if! {
         }
         fn oauth2_callback() -> String { "http://127.0.0.1:8088/callback".into() }
         fn oauth2_client() -> String { "H_ZgxAxDxk7mvYbsd_ub9igbYZEoFNkzEB49VogyQH8".into() }
-        fn oauth2_auth() -> String { "SF9aZ3hBeER4azdtdllic2RfdWI5aWdiWVpFb0ZOa3pFQjQ5Vm9neVFIODo=".into() }
     } else {
         fn init_log() {}
         fn oauth2_callback() -> String { "https://surway.hovland.xyz/callback".into() }
         fn oauth2_client() -> String { "WMCljcpGb8Gr36esjgbzodI9nZ6x49bAfqF5rWDgsBk".into() }
-        fn oauth2_auth() -> String { "V01DbGpjcEdiOEdyMzZlc2pnYnpvZEk5blo2eDQ5YkFmcUY1cldEZ3NCazo=".into() }
     }
 }
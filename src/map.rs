@@ -1,13 +1,15 @@
 use crate::{
     geo::{destination, Coord},
-    osm::OsmNode,
+    osm::{OsmNode, OsmNoteStatus},
+    track::xml_escape,
     Model,
 };
 use ::web_sys::{Element, HtmlAnchorElement};
 use gloo_events::EventListener;
-use js_sys::{Array, Function};
+use js_sys::{Array, Date, Function};
 use leaflet::{
-    Circle, Control, LatLng, LatLngBounds, LayerGroup, Map, Marker, Polyline, Rectangle, TileLayer,
+    Circle, CircleMarker, Control, LatLng, LatLngBounds, LayerGroup, Map, Marker, MouseEvent,
+    Polyline, Rectangle, TileLayer,
 };
 use seed::{prelude::*, window};
 use serde::{Deserialize, Serialize};
@@ -25,6 +27,16 @@ struct CircleOptions {
     radius: f64,
 }
 
+#[derive(Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct CircleMarkerOptions {
+    radius: f64,
+    color: String,
+    weight: u32,
+    fillColor: String,
+    fillOpacity: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 struct MarkerOptions {
     title: String,
@@ -43,16 +55,36 @@ struct ControlProps {
     options: ControlOptions,
 }
 
-pub fn init<T, U>(
+pub fn init<T, U, V>(
     track_position_callback: T,
     wake_lock_callback: Option<U>,
-) -> (Map, LayerGroup, LayerGroup, LayerGroup)
+    map_click_callback: V,
+) -> (
+    Map,
+    LayerGroup,
+    LayerGroup,
+    LayerGroup,
+    LayerGroup,
+    LayerGroup,
+)
 where
     T: Fn() + 'static + Clone,
     U: Fn() + 'static + Clone,
+    V: Fn(Coord) + 'static,
 {
     let map = Map::new("map", &JsValue::NULL);
 
+    let on_click = Closure::wrap(Box::new(move |event: MouseEvent| {
+        let latlng = event.latlng();
+        map_click_callback(Coord {
+            lat: latlng.lat(),
+            lon: latlng.lng(),
+        });
+    }) as Box<dyn FnMut(MouseEvent)>);
+
+    map.on("click", on_click.as_ref());
+    on_click.forget();
+
     let topology_layer_group = LayerGroup::new();
     topology_layer_group.addTo(&map);
 
@@ -62,6 +94,12 @@ where
     let notes_layer_group = LayerGroup::new();
     notes_layer_group.addTo(&map);
 
+    let osm_notes_layer_group = LayerGroup::new();
+    osm_notes_layer_group.addTo(&map);
+
+    let track_layer_group = LayerGroup::new();
+    track_layer_group.addTo(&map);
+
     add_track_position_control(&map, track_position_callback);
 
     if let Some(callback) = wake_lock_callback {
@@ -79,6 +117,8 @@ where
         topology_layer_group,
         position_layer_group,
         notes_layer_group,
+        osm_notes_layer_group,
+        track_layer_group,
     )
 }
 
@@ -100,30 +140,37 @@ pub fn render_topology_and_position(model: &Model) {
     {
         topology_layer_group.clearLayers();
 
-        topology_layer_group.addLayer(&Rectangle::new_with_options(
-            &bbox(chunk_position, model.osm_chunk_radius),
-            &JsValue::from_serde(&LineOptions {
-                color: "red".into(),
-                weight: 2,
-                fillOpacity: 0.0,
-            })
-            .expect("Unable to serialize rectangle options"),
-        ));
+        // There are no chunks to show when surveying a whole file
+        if !model.has_whole_osm_file() {
+            topology_layer_group.addLayer(&Rectangle::new_with_options(
+                &bbox(chunk_position, model.osm_chunk_radius),
+                &JsValue::from_serde(&LineOptions {
+                    color: "red".into(),
+                    weight: 2,
+                    fillOpacity: 0.0,
+                })
+                .expect("Unable to serialize rectangle options"),
+            ));
 
-        topology_layer_group.addLayer(&Rectangle::new_with_options(
-            &bbox(
-                chunk_position,
-                model.osm_chunk_radius * model.osm_chunk_trigger_factor,
-            ),
-            &JsValue::from_serde(&LineOptions {
-                color: "orange".into(),
-                weight: 2,
-                fillOpacity: 0.0,
-            })
-            .expect("Unable to serialize rectangle options"),
-        ));
+            topology_layer_group.addLayer(&Rectangle::new_with_options(
+                &bbox(
+                    chunk_position,
+                    model.osm_chunk_radius * model.osm_chunk_trigger_factor,
+                ),
+                &JsValue::from_serde(&LineOptions {
+                    color: "orange".into(),
+                    weight: 2,
+                    fillOpacity: 0.0,
+                })
+                .expect("Unable to serialize rectangle options"),
+            ));
+        }
+
+        let profile = model.query_profiles.active();
 
         for way in model.osm.ways.iter() {
+            let color = profile.way_color(&way.tags).unwrap_or("green");
+
             topology_layer_group.addLayer(&Polyline::new_with_options(
                 way.points(&model.osm)
                     .into_iter()
@@ -131,13 +178,29 @@ pub fn render_topology_and_position(model: &Model) {
                     .map(JsValue::from)
                     .collect(),
                 &JsValue::from_serde(&LineOptions {
-                    color: "green".into(),
+                    color: color.into(),
                     weight: 3,
                     fillOpacity: 0.0,
                 })
                 .expect("Unable to serialize polyline options"),
             ));
         }
+
+        for node in model.osm.nodes.iter() {
+            if let Some(color) = profile.node_color(&node.tags) {
+                topology_layer_group.addLayer(&CircleMarker::new_with_options(
+                    &LatLng::from(node),
+                    &JsValue::from_serde(&CircleMarkerOptions {
+                        radius: 5.0,
+                        color: color.into(),
+                        weight: 2,
+                        fillColor: color.into(),
+                        fillOpacity: 0.8,
+                    })
+                    .expect("Unable to serialize circle marker options"),
+                ));
+            }
+        }
     }
 
     render_position(model);
@@ -174,6 +237,16 @@ pub fn render_position(model: &Model) {
                 .expect("Unable to serialize circle options"),
         ));
 
+        if let Some(poi) = &model.poi {
+            position_layer_group.addLayer(&Marker::new_with_options(
+                &LatLng::from(poi.position),
+                &JsValue::from_serde(&MarkerOptions {
+                    title: poi.preset.label().into(),
+                })
+                .expect("Unable to serialize marker options"),
+            ));
+        }
+
         topology_layer_group.addTo(map);
         position_layer_group.addTo(map);
     }
@@ -204,6 +277,90 @@ pub fn render_notes(model: &Model) {
     }
 }
 
+pub fn render_osm_notes(model: &Model) {
+    if let (Some(map), Some(osm_notes_layer_group)) = (&model.map, &model.osm_notes_layer_group) {
+        osm_notes_layer_group.clearLayers();
+        let now = Date::now();
+
+        for note in model.osm_notes.iter() {
+            let (status, color) = match note.status {
+                OsmNoteStatus::Open => ("Open", "#e85600"),
+                OsmNoteStatus::Closed => ("Resolved", "#32b643"),
+                OsmNoteStatus::Hidden => continue,
+            };
+
+            let marker = CircleMarker::new_with_options(
+                &LatLng::from(note.position()),
+                &JsValue::from_serde(&CircleMarkerOptions {
+                    radius: 8.0,
+                    color: color.into(),
+                    weight: 2,
+                    fillColor: color.into(),
+                    fillOpacity: 0.5,
+                })
+                .expect("Unable to serialize circle marker options"),
+            );
+
+            let text = note
+                .comments()
+                .first()
+                .map(|comment| xml_escape(&comment.text))
+                .unwrap_or_default();
+
+            marker.bindTooltip(
+                &JsValue::from(format!(
+                    "<b>{} note</b>, created {}<br/>{}",
+                    status,
+                    note.age(now),
+                    text
+                )),
+                &JsValue::from_serde(&PopupOptions {})
+                    .expect("Unable to serialize tooltip options"),
+            );
+
+            // Open the note in a dialog, where it can be commented on
+            let hash = format!("osm-note/{}", note.id);
+            let on_click = Closure::wrap(Box::new(move || {
+                window()
+                    .location()
+                    .set_hash(&hash)
+                    .expect("Unable to set location hash");
+            }) as Box<dyn FnMut()>);
+
+            marker.on("click", on_click.as_ref());
+            on_click.forget();
+
+            osm_notes_layer_group.addLayer(&marker);
+        }
+
+        osm_notes_layer_group.addTo(map);
+    }
+}
+
+pub fn render_track(model: &Model) {
+    if let (Some(map), Some(track_layer_group)) = (&model.map, &model.track_layer_group) {
+        track_layer_group.clearLayers();
+
+        for segment in model.track.segments.iter() {
+            track_layer_group.addLayer(&Polyline::new_with_options(
+                segment
+                    .iter()
+                    .map(|point| LatLng::from(point.position))
+                    .map(JsValue::from)
+                    .collect(),
+                &JsValue::from_serde(&LineOptions {
+                    color: "purple".into(),
+                    weight: 3,
+                    fillOpacity: 0.0,
+                })
+                .expect("Unable to serialize polyline options"),
+            ));
+        }
+
+        track_layer_group.addTo(map);
+    }
+}
+
 fn bbox(position: &Coord, radius: f64) -> LatLngBounds {
     let north = destination(position, 0.0, radius);
     let east = destination(position, 90.0, radius);
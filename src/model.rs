@@ -2,25 +2,38 @@ use leaflet::{LayerGroup, Map};
 use seed::Url;
 use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, fmt};
-use web_sys::WakeLockSentinel;
+use web_sys::{AudioContext, WakeLockSentinel};
 
 use crate::{
-    geo::Coord,
+    alerts::{Alert, AlertMonitor, AlertSettings},
+    chunk_store::ChunkStoreSettings,
+    data_source::DataSourceSettings,
+    geo::{Coord, SegmentIndex},
     js_sys::Date,
-    osm::{OsmDocument, OsmWay},
+    osm::{OsmChange, OsmDocument, OsmError, OsmId, OsmNote, OsmWay},
+    outbox::{Outbox, UploadState},
+    overpass::QueryProfiles,
+    poi::PoiDraft,
+    tag_edit::WayEdit,
+    track::{TraceVisibility, Track},
 };
 
 pub struct Model {
     pub route: Route,
     pub access_token: Option<String>,
+    pub session_expired: bool,
     pub user: Option<User>,
     pub map: Option<Map>,
     pub topology_layer_group: Option<LayerGroup>,
     pub position_layer_group: Option<LayerGroup>,
     pub notes_layer_group: Option<LayerGroup>,
+    pub osm_notes_layer_group: Option<LayerGroup>,
+    pub track_layer_group: Option<LayerGroup>,
     pub osm: OsmDocument,
+    pub segment_index: SegmentIndex,
+    pub osm_error: Option<OsmError>,
     pub position: Coord,
-    pub nearest_way_id: Option<String>,
+    pub nearest_way_id: Option<OsmId>,
     pub start_distance: Option<f64>,
     pub end_distance: Option<f64>,
     pub way_distance: Option<f64>,
@@ -28,23 +41,100 @@ pub struct Model {
     pub osm_chunk_position: Option<Coord>,
     pub osm_chunk_radius: f64,
     pub osm_chunk_trigger_factor: f64,
+    pub osm_eviction_radius: f64,
+    pub data_source: DataSourceSettings,
+    pub local_osm_file: Option<LocalOsmFile>,
+    pub query_profiles: QueryProfiles,
+    pub new_query_profile_name: String,
+    pub stored_chunks_loaded: bool,
+    pub chunk_store_settings: ChunkStoreSettings,
+    pub chunk_store_usage: Option<(usize, usize)>,
     pub notes: VecDeque<Note>,
+    pub osm_notes: Vec<OsmNote>,
+    pub osm_note_comment: String,
+    pub outbox: Outbox,
+    pub poi: Option<PoiDraft>,
+    /// Whether the next tap on the map places the POI being added.
+    pub placing_poi: bool,
+    pub poi_upload: Option<UploadState>,
+    pub way_edit: Option<WayEdit>,
+    pub way_edit_upload: Option<UploadState>,
+    /// The latest version of the edited way, when someone else changed it before we uploaded.
+    pub way_conflict: Option<OsmDocument>,
+    /// Edits saved for later, to upload or export when ready.
+    pub pending_changes: OsmChange,
+    pub pending_comment: String,
+    pub pending_upload: Option<UploadState>,
+    pub alert_settings: AlertSettings,
+    pub alert_monitor: AlertMonitor,
+    /// The latest alert, shown until dismissed when alerts are shown as messages.
+    pub alert: Option<Alert>,
+    /// The latest alerts, newest first.
+    pub alert_history: VecDeque<Alert>,
+    pub new_alert_rule_name: String,
+    pub new_alert_rule: String,
+    pub new_alert_rule_error: Option<String>,
+    /// Only created from a click, since browsers keep an audio context silent unless it is started
+    /// by a user gesture. Until then, alert sounds are skipped.
+    pub audio_context: Option<AudioContext>,
     pub new_note: String,
     pub note_id: Option<NoteId>,
     pub wake_lock_sentinel: Option<WakeLockSentinel>,
+    pub track: Track,
+    /// When the track was last saved to LocalStorage.
+    pub track_saved_at: f64,
+    /// Whether the track has grown too large for LocalStorage, so it is no longer kept there.
+    pub track_unsaved: bool,
+    pub trace_description: String,
+    pub trace_tags: String,
+    pub trace_visibility: TraceVisibility,
+    pub trace_upload: Option<UploadState>,
+}
+
+/// An OSM file picked by the user. The data is kept, so it can be filtered again when switching
+/// map profile.
+pub struct LocalOsmFile {
+    pub name: String,
+    pub data: LocalOsmData,
+}
+
+pub enum LocalOsmData {
+    /// XML or JSON, which is read all at once.
+    Text(String),
+    /// A PBF extract, which is read a chunk at a time.
+    Pbf(Vec<u8>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Route {
     Main,
-    Callback(String),
+    Account,
+    Alerts,
+    Callback {
+        code: Option<String>,
+        state: Option<String>,
+    },
     EditNote,
+    EditWay(OsmId),
+    Edits,
     NewNote,
+    NewPoi,
     Notes,
+    OsmNote(OsmId),
+    Settings,
+    Track,
+    UploadTrack,
 }
 
-#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
-pub struct NoteId(u32);
+/// What to do with a way edit, once we have the latest version of the way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WayEditTarget {
+    Upload,
+    SaveForLater,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct NoteId(pub(crate) u32);
 
 impl NoteId {
     pub fn new() -> NoteId {
@@ -65,6 +155,37 @@ pub struct Note {
     pub position: Coord,
     pub text: String,
     pub uploaded: bool,
+    #[serde(default)]
+    pub osm_note_id: Option<OsmId>,
+    /// Whether the user has agreed to post this note without being logged in.
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+/// A comment on, or a change of status for, a note by someone else. These are queued, so they can
+/// be done while offline.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OsmNoteAction {
+    pub note_id: OsmId,
+    pub kind: OsmNoteActionKind,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum OsmNoteActionKind {
+    Comment,
+    Close,
+    Reopen,
+}
+
+impl OsmNoteActionKind {
+    pub fn path(&self) -> &'static str {
+        match self {
+            OsmNoteActionKind::Comment => "comment",
+            OsmNoteActionKind::Close => "close",
+            OsmNoteActionKind::Reopen => "reopen",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,7 +196,10 @@ pub struct OAuth2Response {
 #[derive(Debug)]
 pub struct User {
     pub name: String,
-    pub photo: String,
+    pub photo: Option<String>,
+    pub account_created: String,
+    pub changeset_count: u32,
+    pub unread_messages: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -86,7 +210,14 @@ pub struct UserResponse {
 #[derive(Debug, Deserialize)]
 pub struct UserResponseUser {
     pub display_name: String,
-    pub img: UserResponseImg,
+    #[serde(default)]
+    pub account_created: String,
+    #[serde(default)]
+    pub img: Option<UserResponseImg>,
+    #[serde(default)]
+    pub changesets: UserResponseCount,
+    #[serde(default)]
+    pub messages: UserResponseMessages,
 }
 
 #[derive(Debug, Deserialize)]
@@ -94,55 +225,123 @@ pub struct UserResponseImg {
     pub href: String,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct UserResponseCount {
+    pub count: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UserResponseMessages {
+    #[serde(default)]
+    pub received: UserResponseReceived,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UserResponseReceived {
+    pub count: u32,
+    pub unread: u32,
+}
+
+impl From<UserResponseUser> for User {
+    fn from(user: UserResponseUser) -> Self {
+        User {
+            name: user.display_name,
+            photo: user.img.map(|img| img.href),
+            account_created: user.account_created,
+            changeset_count: user.changesets.count,
+            unread_messages: user.messages.received.unread,
+        }
+    }
+}
+
 impl Model {
     pub fn find_nearest_way(&self) -> Option<&OsmWay> {
-        let nearest_points = self.find_nearest_point_on_each_way();
+        let (_, _, way_id) = *self.segment_index.nearest(&self.position, 1).first()?;
+        self.osm.way(way_id)
+    }
+
+    pub fn find_nearby_ways(&self, radius: f64) -> Vec<(&OsmWay, f64)> {
+        self.segment_index
+            .within(&self.position, radius)
+            .into_iter()
+            .filter_map(|(_, distance, way_id)| Some((self.osm.way(way_id)?, distance)))
+            .collect()
+    }
+
+    /// Replaces notes we already have with their downloaded versions, and forgets notes far away.
+    pub fn merge_osm_notes(&mut self, notes: Vec<OsmNote>) {
+        let ids: Vec<OsmId> = notes.iter().map(|note| note.id).collect();
+        self.osm_notes.retain(|note| !ids.contains(&note.id));
+        self.osm_notes.extend(notes);
 
-        let (_, _, way) = nearest_points.iter().min_by(|(_, x, _), (_, y, _)| {
-            x.partial_cmp(y).expect("Could not compare distances")
-        })?;
+        let bbox = self.position.bbox(self.osm_eviction_radius);
+        self.osm_notes
+            .retain(|note| bbox.contains(&note.position()));
+    }
+
+    pub fn is_using_local_file(&self) -> bool {
+        matches!(self.data_source, DataSourceSettings::LocalFile(_))
+    }
+
+    /// Whether all the OSM data there is has been loaded, so nothing should be evicted.
+    pub fn has_whole_osm_file(&self) -> bool {
+        matches!(
+            self.local_osm_file,
+            Some(LocalOsmFile {
+                data: LocalOsmData::Text(_),
+                ..
+            })
+        )
+    }
 
-        Some(way)
+    /// Identifies the map data downloaded with the current data source and map profile.
+    pub fn chunk_key(&self) -> String {
+        self.data_source
+            .source()
+            .chunk_key(&self.query_profiles.active().query())
     }
 
     pub fn is_outside_osm_trigger_box(&self) -> bool {
         if let Some(chunk_pos) = &self.osm_chunk_position {
             let radius = self.osm_chunk_radius * self.osm_chunk_trigger_factor;
-            let bbox = chunk_pos.bbox(radius);
-
-            self.position.lat > bbox.upper_right.lat
-                || self.position.lon > bbox.upper_right.lon
-                || self.position.lat < bbox.lower_left.lat
-                || self.position.lon < bbox.lower_left.lon
+            !chunk_pos.bbox(radius).contains(&self.position)
         } else {
             true
         }
     }
-
-    fn find_nearest_point_on_each_way(&self) -> Vec<(Coord, f64, &OsmWay)> {
-        self.osm
-            .ways
-            .iter()
-            .map(|way| way.find_nearest_point(&self.position, &self.osm))
-            .collect()
-    }
 }
 
 impl From<Url> for Route {
     fn from(mut url: Url) -> Self {
         match url.remaining_path_parts().as_slice() {
-            ["callback"] => Self::Callback(
-                url.search()
-                    .get("code")
-                    .expect("No auth codes provided.")
-                    .first()
-                    .expect("No auth code provided.")
-                    .clone(),
-            ),
+            ["callback"] => {
+                let search = url.search();
+                let first = |key: &str| search.get(key).and_then(|values| values.first()).cloned();
+
+                Self::Callback {
+                    code: first("code"),
+                    state: first("state"),
+                }
+            }
             _ => match url.remaining_hash_path_parts().as_slice() {
+                ["account"] => Self::Account,
+                ["alerts"] => Self::Alerts,
                 ["edit-note"] => Self::EditNote,
+                ["edits"] => Self::Edits,
+                ["edit-way", id] => match id.parse() {
+                    Ok(id) => Self::EditWay(id),
+                    Err(_) => Self::Main,
+                },
                 ["new-note"] => Self::NewNote,
+                ["new-poi"] => Self::NewPoi,
                 ["notes"] => Self::Notes,
+                ["osm-note", id] => match id.parse() {
+                    Ok(id) => Self::OsmNote(id),
+                    Err(_) => Self::Main,
+                },
+                ["settings"] => Self::Settings,
+                ["track"] => Self::Track,
+                ["upload-track"] => Self::UploadTrack,
                 _ => Self::Main,
             },
         }
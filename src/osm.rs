@@ -1,4 +1,15 @@
-use serde::Deserialize;
+use crate::{
+    geo::{BoundingBox, Coord},
+    pbf,
+    track::xml_escape,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+};
+
+pub type OsmId = i64;
 
 #[derive(Debug, Deserialize)]
 pub struct OsmDocument {
@@ -6,49 +17,586 @@ pub struct OsmDocument {
     pub nodes: Vec<OsmNode>,
     #[serde(rename = "way", default)]
     pub ways: Vec<OsmWay>,
+    #[serde(rename = "relation", default)]
+    pub relations: Vec<OsmRelation>,
+    #[serde(default)]
+    remark: Option<String>,
+    #[serde(skip)]
+    node_index: HashMap<OsmId, usize>,
+    #[serde(skip)]
+    way_index: HashMap<OsmId, usize>,
 }
 
-#[derive(Debug, Deserialize)]
+trait OsmElement {
+    fn id(&self) -> OsmId;
+    fn version(&self) -> Option<u32>;
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct OsmNode {
-    pub id: String,
+    pub id: OsmId,
+    #[serde(default)]
+    pub version: Option<u32>,
     pub lat: f64,
     pub lon: f64,
+    #[serde(rename = "tag", default)]
+    pub tags: Vec<OsmTag>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct OsmWay {
-    pub id: String,
+    pub id: OsmId,
+    #[serde(default)]
+    pub version: Option<u32>,
     #[serde(rename = "nd", default)]
     pub nds: Vec<OsmNd>,
     #[serde(rename = "tag", default)]
     pub tags: Vec<OsmTag>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct OsmNd {
     #[serde(rename = "ref", default)]
-    pub node_ref: String,
+    pub node_ref: OsmId,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OsmRelation {
+    pub id: OsmId,
+    #[serde(default)]
+    pub version: Option<u32>,
+    #[serde(rename = "member", default)]
+    pub members: Vec<OsmMember>,
+    #[serde(rename = "tag", default)]
+    pub tags: Vec<OsmTag>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OsmMember {
+    #[serde(rename = "type")]
+    pub member_type: OsmMemberType,
+    #[serde(rename = "ref")]
+    pub member_ref: OsmId,
+    #[serde(default)]
+    pub role: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OsmMemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct OsmTag {
     pub k: String,
     pub v: String,
 }
 
+/// A note from the OSM notes API, as opposed to the notes taken locally in this app.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OsmNote {
+    pub id: OsmId,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(deserialize_with = "deserialize_note_status")]
+    pub status: OsmNoteStatus,
+    pub date_created: String,
+    #[serde(default)]
+    pub date_closed: Option<String>,
+    #[serde(default)]
+    comments: OsmNoteComments,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OsmNoteStatus {
+    Open,
+    Closed,
+    Hidden,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct OsmNoteComments {
+    #[serde(rename = "comment", default)]
+    comments: Vec<OsmNoteComment>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OsmNoteComment {
+    pub date: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    pub action: String,
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsmNoteDocument {
+    #[serde(rename = "note", default)]
+    notes: Vec<OsmNote>,
+}
+
+#[derive(Debug)]
+pub enum OsmError {
+    Syntax(String),
+    MissingNode { way: OsmId, node: OsmId },
+    Overpass(String),
+    Unsupported(String),
+    Empty,
+}
+
+impl fmt::Display for OsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsmError::Syntax(message) => write!(f, "Unable to read the OSM data: {}", message),
+            OsmError::MissingNode { way, node } => write!(
+                f,
+                "The OSM data is incomplete: way {} refers to missing node {}",
+                way, node
+            ),
+            OsmError::Overpass(remark) => write!(f, "Overpass failed: {}", remark),
+            OsmError::Unsupported(what) => write!(f, "Surway doesn't support {}", what),
+            OsmError::Empty => write!(f, "No OSM data was found in this area"),
+        }
+    }
+}
+
+impl From<quick_xml::DeError> for OsmError {
+    fn from(error: quick_xml::DeError) -> Self {
+        OsmError::Syntax(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for OsmError {
+    fn from(error: serde_json::Error) -> Self {
+        OsmError::Syntax(error.to_string())
+    }
+}
+
+/// The `[out:json]` format of Overpass and the OSM API, where all elements are in one array.
+#[derive(Deserialize)]
+struct JsonDocument {
+    #[serde(default)]
+    elements: Vec<JsonElement>,
+    #[serde(default)]
+    remark: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonElement {
+    #[serde(rename = "type")]
+    element_type: OsmMemberType,
+    id: OsmId,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    lat: Option<f64>,
+    #[serde(default)]
+    lon: Option<f64>,
+    #[serde(default)]
+    nodes: Vec<OsmId>,
+    #[serde(default)]
+    members: Vec<OsmMember>,
+    #[serde(default, deserialize_with = "deserialize_json_tags")]
+    tags: Vec<OsmTag>,
+}
+
+/// Reads a JSON tag object straight into tags, keeping their order.
+fn deserialize_json_tags<'de, D>(deserializer: D) -> Result<Vec<OsmTag>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TagVisitor;
+
+    impl<'de> de::Visitor<'de> for TagVisitor {
+        type Value = Vec<OsmTag>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an object of tags")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: de::MapAccess<'de>,
+        {
+            let mut tags = Vec::with_capacity(map.size_hint().unwrap_or(0));
+
+            while let Some((k, v)) = map.next_entry()? {
+                tags.push(OsmTag { k, v });
+            }
+
+            Ok(tags)
+        }
+    }
+
+    deserializer.deserialize_map(TagVisitor)
+}
+
 impl OsmDocument {
     pub fn new() -> OsmDocument {
         OsmDocument {
             nodes: vec![],
             ways: vec![],
+            relations: vec![],
+            remark: None,
+            node_index: HashMap::new(),
+            way_index: HashMap::new(),
         }
     }
 
-    fn node(&self, id: &str) -> &OsmNode {
-        self.nodes
+    pub fn from_xml(xml: &str) -> Result<OsmDocument, OsmError> {
+        let osm = OsmDocument::read_xml(xml)?;
+        osm.validate()?;
+        Ok(osm)
+    }
+
+    pub fn from_json(json: &str) -> Result<OsmDocument, OsmError> {
+        let osm = OsmDocument::read_json(json)?;
+        osm.validate()?;
+        Ok(osm)
+    }
+
+    fn read_xml(xml: &str) -> Result<OsmDocument, OsmError> {
+        let mut osm: OsmDocument = quick_xml::de::from_str(xml)?;
+        osm.build_index();
+        Ok(osm)
+    }
+
+    fn read_json(json: &str) -> Result<OsmDocument, OsmError> {
+        let document: JsonDocument = serde_json::from_str(json)?;
+        let mut osm = OsmDocument::new();
+        osm.remark = document.remark;
+
+        for element in document.elements {
+            match element.element_type {
+                OsmMemberType::Node => match (element.lat, element.lon) {
+                    (Some(lat), Some(lon)) => osm.nodes.push(OsmNode {
+                        id: element.id,
+                        version: element.version,
+                        lat,
+                        lon,
+                        tags: element.tags,
+                    }),
+                    _ => {
+                        return Err(OsmError::Syntax(format!(
+                            "node {} has no position",
+                            element.id
+                        )))
+                    }
+                },
+                OsmMemberType::Way => osm.ways.push(OsmWay {
+                    id: element.id,
+                    version: element.version,
+                    nds: element
+                        .nodes
+                        .into_iter()
+                        .map(|node_ref| OsmNd { node_ref })
+                        .collect(),
+                    tags: element.tags,
+                }),
+                OsmMemberType::Relation => osm.relations.push(OsmRelation {
+                    id: element.id,
+                    version: element.version,
+                    members: element.members,
+                    tags: element.tags,
+                }),
+            }
+        }
+
+        osm.build_index();
+        Ok(osm)
+    }
+
+    /// Reads the part of a PBF extract around the bounding box.
+    pub fn from_pbf(data: &[u8], bbox: &BoundingBox) -> Result<OsmDocument, OsmError> {
+        let bounds = pbf::Bounds {
+            min_lat: bbox.lower_left.lat,
+            min_lon: bbox.lower_left.lon,
+            max_lat: bbox.upper_right.lat,
+            max_lon: bbox.upper_right.lon,
+        };
+
+        let data = pbf::read(data, &bounds).map_err(OsmError::Syntax)?;
+        let tags = |tags: Vec<(String, String)>| {
+            tags.into_iter()
+                .map(|(k, v)| OsmTag { k, v })
+                .collect::<Vec<_>>()
+        };
+
+        let mut osm = OsmDocument::new();
+
+        osm.nodes = data
+            .nodes
+            .into_iter()
+            .map(|node| OsmNode {
+                id: node.id,
+                version: node.version,
+                lat: node.lat,
+                lon: node.lon,
+                tags: tags(node.tags),
+            })
+            .collect();
+
+        osm.ways = data
+            .ways
+            .into_iter()
+            .map(|way| OsmWay {
+                id: way.id,
+                version: way.version,
+                nds: way
+                    .refs
+                    .into_iter()
+                    .map(|node_ref| OsmNd { node_ref })
+                    .collect(),
+                tags: tags(way.tags),
+            })
+            .collect();
+
+        osm.relations = data
+            .relations
+            .into_iter()
+            .map(|relation| OsmRelation {
+                id: relation.id,
+                version: relation.version,
+                members: relation
+                    .members
+                    .into_iter()
+                    .map(|member| OsmMember {
+                        member_type: match member.member_type {
+                            pbf::PbfMemberType::Node => OsmMemberType::Node,
+                            pbf::PbfMemberType::Way => OsmMemberType::Way,
+                            pbf::PbfMemberType::Relation => OsmMemberType::Relation,
+                        },
+                        member_ref: member.id,
+                        role: member.role,
+                    })
+                    .collect(),
+                tags: tags(relation.tags),
+            })
+            .collect();
+
+        osm.build_index();
+        osm.validate()?;
+        Ok(osm)
+    }
+
+    /// Reads either format, telling them apart by the first character.
+    pub fn parse(data: &str) -> Result<OsmDocument, OsmError> {
+        if data.trim_start().starts_with('{') {
+            OsmDocument::from_json(data)
+        } else {
+            OsmDocument::from_xml(data)
+        }
+    }
+
+    /// Reads an extract cut at a bounding box, like the PBF reader does. Ways going on outside the
+    /// extract lose their references to nodes that aren't in it, and are left out if fewer than
+    /// two nodes remain.
+    pub fn parse_extract(data: &str) -> Result<OsmDocument, OsmError> {
+        let mut osm = if data.trim_start().starts_with('{') {
+            OsmDocument::read_json(data)?
+        } else {
+            OsmDocument::read_xml(data)?
+        };
+
+        let node_index = &osm.node_index;
+
+        for way in osm.ways.iter_mut() {
+            way.nds.retain(|nd| node_index.contains_key(&nd.node_ref));
+        }
+
+        osm.ways.retain(|way| way.nds.len() >= 2);
+        osm.build_index();
+        osm.validate()?;
+        Ok(osm)
+    }
+
+    fn validate(&self) -> Result<(), OsmError> {
+        // Overpass reports timeouts and out-of-memory conditions as a remark in an otherwise valid document
+        if let Some(remark) = &self.remark {
+            if remark.contains("runtime error") {
+                return Err(OsmError::Overpass(remark.trim().into()));
+            }
+        }
+
+        if self.nodes.is_empty() && self.ways.is_empty() {
+            return Err(OsmError::Empty);
+        }
+
+        for way in self.ways.iter() {
+            if let Some(nd) = way.nds.iter().find(|nd| self.node(nd.node_ref).is_none()) {
+                return Err(OsmError::MissingNode {
+                    way: way.id,
+                    node: nd.node_ref,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_index(&mut self) {
+        self.node_index = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+
+        self.way_index = self
+            .ways
             .iter()
-            .find(|node| node.id == id)
-            .unwrap_or_else(|| panic!("Didn't find a node with id {}", id))
+            .enumerate()
+            .map(|(i, way)| (way.id, i))
+            .collect();
+    }
+
+    /// Merges a newly downloaded chunk into this document. Elements present in both are replaced by
+    /// the incoming ones, unless the incoming element is known to be an older version.
+    pub fn merge(&mut self, other: OsmDocument) {
+        merge_elements(&mut self.nodes, other.nodes);
+        merge_elements(&mut self.ways, other.ways);
+        merge_elements(&mut self.relations, other.relations);
+        self.build_index();
+    }
+
+    /// Drops ways that are entirely outside the given radius, along with the nodes and relations
+    /// only they were using. A way crossing the area is kept, even if all its nodes are outside.
+    pub fn evict(&mut self, center: &Coord, radius: f64) {
+        let bbox = center.bbox(radius);
+
+        self.retain(
+            |osm, way| {
+                let points = way.points(osm);
+
+                points.iter().any(|&node| bbox.contains(&node.into()))
+                    || points.windows(2).any(|segment| {
+                        bbox.intersects(&BoundingBox::spanning(
+                            &segment[0].into(),
+                            &segment[1].into(),
+                        ))
+                    })
+            },
+            |node| bbox.contains(&node.into()),
+        );
+    }
+
+    /// Drops the ways not matching the predicate, along with the untagged nodes and the relations
+    /// only they were using. Used for data sources that don't filter what they return.
+    pub fn retain_ways(&mut self, keep: impl Fn(&OsmWay) -> bool) {
+        self.retain(|_, way| keep(way), |node| !node.tags.is_empty());
+    }
+
+    fn retain(
+        &mut self,
+        keep_way: impl Fn(&OsmDocument, &OsmWay) -> bool,
+        keep_node: impl Fn(&OsmNode) -> bool,
+    ) {
+        let kept_ways: Vec<OsmWay> = std::mem::take(&mut self.ways)
+            .into_iter()
+            .filter(|way| keep_way(self, way))
+            .collect();
+
+        let used_nodes: HashSet<OsmId> = kept_ways
+            .iter()
+            .flat_map(|way| way.nds.iter().map(|nd| nd.node_ref))
+            .collect();
+
+        self.nodes
+            .retain(|node| used_nodes.contains(&node.id) || keep_node(node));
+
+        let kept_nodes: HashSet<OsmId> = self.nodes.iter().map(|node| node.id).collect();
+        let kept_way_ids: HashSet<OsmId> = kept_ways.iter().map(|way| way.id).collect();
+
+        self.relations.retain(|relation| {
+            relation
+                .members
+                .iter()
+                .any(|member| match member.member_type {
+                    OsmMemberType::Node => kept_nodes.contains(&member.member_ref),
+                    OsmMemberType::Way => kept_way_ids.contains(&member.member_ref),
+                    OsmMemberType::Relation => false,
+                })
+        });
+
+        self.ways = kept_ways;
+        self.build_index();
+    }
+
+    fn node(&self, id: OsmId) -> Option<&OsmNode> {
+        self.node_index.get(&id).map(|&i| &self.nodes[i])
+    }
+
+    pub fn way(&self, id: OsmId) -> Option<&OsmWay> {
+        self.way_index.get(&id).map(|&i| &self.ways[i])
+    }
+
+    /// Replaces the uploaded elements with the ids and versions OSM gave them.
+    pub fn apply_upload(&mut self, change: &OsmChange, diff: &DiffResult) {
+        // New elements are only kept under their placeholder ids until they are uploaded. Other
+        // edits saved for later keep theirs.
+        let node_ids: HashSet<OsmId> = change.create.nodes.iter().map(|node| node.id).collect();
+        let way_ids: HashSet<OsmId> = change.create.ways.iter().map(|way| way.id).collect();
+        self.nodes.retain(|node| !node_ids.contains(&node.id));
+        self.ways.retain(|way| !way_ids.contains(&way.id));
+        self.merge(change.uploaded(diff));
+    }
+}
+
+fn merge_elements<T: OsmElement>(existing: &mut Vec<T>, incoming: Vec<T>) {
+    let index: HashMap<OsmId, usize> = existing
+        .iter()
+        .enumerate()
+        .map(|(i, element)| (element.id(), i))
+        .collect();
+
+    for element in incoming {
+        match index.get(&element.id()) {
+            Some(&i) => {
+                let is_older = matches!(
+                    (element.version(), existing[i].version()),
+                    (Some(new), Some(old)) if new < old
+                );
+
+                if !is_older {
+                    existing[i] = element;
+                }
+            }
+            None => existing.push(element),
+        }
+    }
+}
+
+impl OsmElement for OsmNode {
+    fn id(&self) -> OsmId {
+        self.id
+    }
+
+    fn version(&self) -> Option<u32> {
+        self.version
+    }
+}
+
+impl OsmElement for OsmWay {
+    fn id(&self) -> OsmId {
+        self.id
+    }
+
+    fn version(&self) -> Option<u32> {
+        self.version
+    }
+}
+
+impl OsmElement for OsmRelation {
+    fn id(&self) -> OsmId {
+        self.id
+    }
+
+    fn version(&self) -> Option<u32> {
+        self.version
     }
 }
 
@@ -56,7 +604,7 @@ impl OsmWay {
     pub fn points<'a>(&'a self, osm: &'a OsmDocument) -> Vec<&OsmNode> {
         self.nds
             .iter()
-            .map(move |nd| osm.node(&nd.node_ref))
+            .filter_map(move |nd| osm.node(nd.node_ref))
             .collect()
     }
 
@@ -67,4 +615,1111 @@ impl OsmWay {
     pub fn end<'a>(&'a self, osm: &'a OsmDocument) -> Option<&OsmNode> {
         self.points(osm).last().copied()
     }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.k == key)
+            .map(|tag| tag.v.as_str())
+    }
+
+    pub fn label(&self) -> String {
+        self.tag("name")
+            .or_else(|| self.tag("ref"))
+            .or_else(|| self.tag("highway"))
+            .map(String::from)
+            .unwrap_or_else(|| format!("way {}", self.id))
+    }
+
+    pub fn relations<'a>(&'a self, osm: &'a OsmDocument) -> Vec<&OsmRelation> {
+        osm.relations
+            .iter()
+            .filter(|relation| relation.has_member(OsmMemberType::Way, self.id))
+            .collect()
+    }
+
+    pub fn routes<'a>(&'a self, osm: &'a OsmDocument) -> Vec<&OsmRelation> {
+        self.relations(osm)
+            .into_iter()
+            .filter(|relation| relation.tag("type") == Some("route"))
+            .collect()
+    }
+}
+
+impl OsmRelation {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.k == key)
+            .map(|tag| tag.v.as_str())
+    }
+
+    pub fn has_member(&self, member_type: OsmMemberType, id: OsmId) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.member_type == member_type && member.member_ref == id)
+    }
+
+    pub fn role_of(&self, member_type: OsmMemberType, id: OsmId) -> Option<&str> {
+        self.members
+            .iter()
+            .find(|member| member.member_type == member_type && member.member_ref == id)
+            .map(|member| member.role.as_str())
+    }
+
+    pub fn label(&self) -> String {
+        match (self.tag("route"), self.tag("ref"), self.tag("name")) {
+            (Some(route), Some(reference), _) => format!("{} {}", route, reference),
+            (Some(route), None, Some(name)) => format!("{} {}", route, name),
+            (_, _, Some(name)) => name.into(),
+            (_, Some(reference), None) => reference.into(),
+            (Some(route), None, None) => route.into(),
+            (None, None, None) => format!("relation {}", self.id),
+        }
+    }
+}
+
+impl OsmNote {
+    pub fn list_from_xml(xml: &str) -> Result<Vec<OsmNote>, OsmError> {
+        let document: OsmNoteDocument = quick_xml::de::from_str(xml)?;
+        Ok(document.notes)
+    }
+
+    pub fn position(&self) -> Coord {
+        Coord {
+            lat: self.lat,
+            lon: self.lon,
+        }
+    }
+
+    pub fn comments(&self) -> &[OsmNoteComment] {
+        &self.comments.comments
+    }
+
+    /// Describes how long ago the note was created, like "3 days ago".
+    pub fn age(&self, now: f64) -> String {
+        let days = match parse_osm_time(&self.date_created) {
+            Some(created) => ((now - created) / 1000.0 / 60.0 / 60.0 / 24.0).floor() as i64,
+            None => return "at an unknown time".into(),
+        };
+
+        let (count, unit) = match days {
+            i64::MIN..=0 => return "today".into(),
+            1..=30 => (days, "day"),
+            31..=364 => (days / 30, "month"),
+            _ => (days / 365, "year"),
+        };
+
+        format!(
+            "{} {}{} ago",
+            count,
+            unit,
+            if count == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// The nodes and ways in one section of an osmChange document.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct OsmChangeElements {
+    #[serde(rename = "node", default)]
+    pub nodes: Vec<OsmNode>,
+    #[serde(rename = "way", default)]
+    pub ways: Vec<OsmWay>,
+    // Only read to tell that an imported file has something we can't handle
+    #[serde(rename = "relation", default)]
+    relations: Vec<OsmRelation>,
+}
+
+impl OsmChangeElements {
+    fn len(&self) -> usize {
+        self.nodes.len() + self.ways.len() + self.relations.len()
+    }
+}
+
+/// Edits to upload to OSM, in the osmChange format. New elements have negative ids, until the
+/// upload gives them real ones.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct OsmChange {
+    #[serde(default)]
+    pub create: OsmChangeElements,
+    #[serde(default)]
+    pub modify: OsmChangeElements,
+    #[serde(default)]
+    delete: OsmChangeElements,
+}
+
+impl OsmChange {
+    pub fn from_xml(xml: &str) -> Result<OsmChange, OsmError> {
+        let change: OsmChange = quick_xml::de::from_str(xml)?;
+
+        if change.delete.len() > 0 {
+            Err(OsmError::Unsupported("deleting elements".into()))
+        } else if !change.create.relations.is_empty() || !change.modify.relations.is_empty() {
+            Err(OsmError::Unsupported("editing relations".into()))
+        } else {
+            Ok(change)
+        }
+    }
+
+    /// The osmChange document, for uploading to the given changeset, or for saving to a file when
+    /// there is no changeset yet.
+    pub fn to_xml(&self, changeset: Option<OsmId>) -> String {
+        let mut xml = String::new();
+        xml.push_str("<osmChange version=\"0.6\" generator=\"Surway\">\n");
+
+        for (name, elements) in [("create", &self.create), ("modify", &self.modify)] {
+            if elements.len() == 0 {
+                continue;
+            }
+
+            writeln!(xml, "  <{}>", name).unwrap();
+
+            for node in elements.nodes.iter() {
+                write_element_start(&mut xml, "node", node.id, node.version, changeset);
+                write!(xml, " lat=\"{}\" lon=\"{}\"", node.lat, node.lon).unwrap();
+
+                if node.tags.is_empty() {
+                    xml.push_str("/>\n");
+                } else {
+                    xml.push_str(">\n");
+                    write_tags(&mut xml, &node.tags, "      ");
+                    xml.push_str("    </node>\n");
+                }
+            }
+
+            for way in elements.ways.iter() {
+                write_element_start(&mut xml, "way", way.id, way.version, changeset);
+                xml.push_str(">\n");
+
+                for nd in way.nds.iter() {
+                    writeln!(xml, "      <nd ref=\"{}\"/>", nd.node_ref).unwrap();
+                }
+
+                write_tags(&mut xml, &way.tags, "      ");
+                xml.push_str("    </way>\n");
+            }
+
+            writeln!(xml, "  </{}>", name).unwrap();
+        }
+
+        xml.push_str("</osmChange>\n");
+        xml
+    }
+
+    pub fn len(&self) -> usize {
+        self.create.len() + self.modify.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a new node, giving it a placeholder id.
+    pub fn create_node(&mut self, mut node: OsmNode) -> OsmId {
+        node.id = self.next_new_id();
+        self.create.nodes.push(node);
+        self.create.nodes[self.create.nodes.len() - 1].id
+    }
+
+    /// A change adding a single node, with a placeholder id apart from the new elements in
+    /// `other`, as both may be on the map at the same time.
+    pub fn new_node(mut node: OsmNode, other: &OsmChange) -> OsmChange {
+        let mut change = OsmChange::default();
+        node.id = other.next_new_id();
+        change.create.nodes.push(node);
+        change
+    }
+
+    /// Adds a changed way, replacing earlier changes to it.
+    pub fn modify_way(&mut self, way: OsmWay) {
+        replace_or_push(&mut self.modify.ways, way);
+    }
+
+    /// Adds the edits from another change, such as an imported file. New elements are given
+    /// placeholder ids that don't clash with the ones we have.
+    pub fn merge(&mut self, other: OsmChange) {
+        let mut new_ids = HashMap::new();
+
+        for node in other.create.nodes {
+            let old_id = node.id;
+            let new_id = self.create_node(node);
+            new_ids.insert(old_id, new_id);
+        }
+
+        for mut way in other.create.ways {
+            for nd in way.nds.iter_mut() {
+                if let Some(&new_id) = new_ids.get(&nd.node_ref) {
+                    nd.node_ref = new_id;
+                }
+            }
+
+            way.id = self.next_new_id();
+            self.create.ways.push(way);
+        }
+
+        for node in other.modify.nodes {
+            replace_or_push(&mut self.modify.nodes, node);
+        }
+
+        for way in other.modify.ways {
+            self.modify_way(way);
+        }
+    }
+
+    /// Forgets the edits that have been uploaded, keeping any made in the meantime.
+    pub fn remove_uploaded(&mut self, uploaded: &OsmChange) {
+        for (elements, uploaded) in [
+            (&mut self.create, &uploaded.create),
+            (&mut self.modify, &uploaded.modify),
+        ] {
+            let node_ids: HashSet<OsmId> = uploaded.nodes.iter().map(|node| node.id).collect();
+            let way_ids: HashSet<OsmId> = uploaded.ways.iter().map(|way| way.id).collect();
+            elements.nodes.retain(|node| !node_ids.contains(&node.id));
+            elements.ways.retain(|way| !way_ids.contains(&way.id));
+        }
+    }
+
+    /// The edited elements as a document, so they can be shown before they are uploaded.
+    pub fn to_document(&self) -> OsmDocument {
+        let mut osm = OsmDocument::new();
+
+        for elements in [&self.create, &self.modify] {
+            osm.nodes.extend(elements.nodes.iter().cloned());
+            osm.ways.extend(elements.ways.iter().cloned());
+        }
+
+        osm.build_index();
+        osm
+    }
+
+    /// The edited elements, with the ids and versions the upload gave them.
+    pub fn uploaded(&self, diff: &DiffResult) -> OsmDocument {
+        let mut osm = self.to_document();
+
+        for node in osm.nodes.iter_mut() {
+            if let Some((id, version)) = diff.new_id_and_version(&diff.nodes, node.id) {
+                node.id = id;
+                node.version = Some(version);
+            }
+        }
+
+        for way in osm.ways.iter_mut() {
+            if let Some((id, version)) = diff.new_id_and_version(&diff.ways, way.id) {
+                way.id = id;
+                way.version = Some(version);
+            }
+
+            for nd in way.nds.iter_mut() {
+                if let Some((id, _)) = diff.new_id_and_version(&diff.nodes, nd.node_ref) {
+                    nd.node_ref = id;
+                }
+            }
+        }
+
+        osm.build_index();
+        osm
+    }
+
+    fn next_new_id(&self) -> OsmId {
+        let lowest = self
+            .create
+            .nodes
+            .iter()
+            .map(|node| node.id)
+            .chain(self.create.ways.iter().map(|way| way.id))
+            .min()
+            .unwrap_or(0);
+
+        lowest.min(0) - 1
+    }
+}
+
+fn replace_or_push<T: OsmElement>(elements: &mut Vec<T>, element: T) {
+    match elements.iter().position(|e| e.id() == element.id()) {
+        Some(i) => elements[i] = element,
+        None => elements.push(element),
+    }
+}
+
+fn write_element_start(
+    xml: &mut String,
+    name: &str,
+    id: OsmId,
+    version: Option<u32>,
+    changeset: Option<OsmId>,
+) {
+    write!(xml, "    <{} id=\"{}\"", name, id).unwrap();
+
+    if let Some(version) = version {
+        write!(xml, " version=\"{}\"", version).unwrap();
+    }
+
+    if let Some(changeset) = changeset {
+        write!(xml, " changeset=\"{}\"", changeset).unwrap();
+    }
+}
+
+/// The document to open a changeset with.
+pub fn changeset_xml(tags: &[OsmTag]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<osm>\n  <changeset>\n");
+    write_tags(&mut xml, tags, "    ");
+    xml.push_str("  </changeset>\n</osm>\n");
+    xml
+}
+
+fn write_tags(xml: &mut String, tags: &[OsmTag], indent: &str) {
+    for tag in tags {
+        writeln!(
+            xml,
+            "{}<tag k=\"{}\" v=\"{}\"/>",
+            indent,
+            xml_escape(&tag.k),
+            xml_escape(&tag.v)
+        )
+        .unwrap();
+    }
+}
+
+/// What an osmChange upload did to each element.
+#[derive(Debug, Deserialize)]
+pub struct DiffResult {
+    #[serde(rename = "node", default)]
+    pub nodes: Vec<DiffResultElement>,
+    #[serde(rename = "way", default)]
+    pub ways: Vec<DiffResultElement>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct DiffResultElement {
+    pub old_id: OsmId,
+    #[serde(default)]
+    pub new_id: Option<OsmId>,
+    #[serde(default)]
+    pub new_version: Option<u32>,
+}
+
+impl DiffResult {
+    pub fn from_xml(xml: &str) -> Result<DiffResult, OsmError> {
+        Ok(quick_xml::de::from_str(xml)?)
+    }
+
+    fn new_id_and_version(
+        &self,
+        elements: &[DiffResultElement],
+        old_id: OsmId,
+    ) -> Option<(OsmId, u32)> {
+        let element = elements.iter().find(|element| element.old_id == old_id)?;
+        Some((element.new_id?, element.new_version?))
+    }
+}
+
+// The status is element text rather than an attribute, which quick-xml can't map to an enum directly.
+fn deserialize_note_status<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<OsmNoteStatus, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "open" => Ok(OsmNoteStatus::Open),
+        "closed" => Ok(OsmNoteStatus::Closed),
+        "hidden" => Ok(OsmNoteStatus::Hidden),
+        other => Err(de::Error::unknown_variant(
+            other,
+            &["open", "closed", "hidden"],
+        )),
+    }
+}
+
+/// Parses timestamps like "2021-12-24 13:37:00 UTC" into milliseconds since the Unix epoch.
+fn parse_osm_time(time: &str) -> Option<f64> {
+    let mut fields = time
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|field| !field.is_empty())
+        .map(|field| field.parse::<i64>());
+
+    let mut next = || fields.next()?.ok();
+    let (year, month, day) = (next()?, next()?, next()?);
+    let (hour, minute, second) = (next()?, next()?, next()?);
+
+    // Days from civil, from http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(((days * 86400 + hour * 3600 + minute * 60 + second) * 1000) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="Overpass API">
+  <node id="1" lat="60.39" lon="5.32"/>
+  <node id="2" lat="60.40" lon="5.33"/>
+  <node id="3" lat="60.41" lon="5.34"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="primary"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="footway"/>
+  </way>
+  <relation id="100">
+    <member type="node" ref="1" role="stop"/>
+    <member type="way" ref="10" role=""/>
+    <tag k="type" v="route"/>
+    <tag k="route" v="bus"/>
+    <tag k="ref" v="5"/>
+  </relation>
+  <relation id="101">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="2" role="via"/>
+    <member type="way" ref="11" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="no_left_turn"/>
+  </relation>
+</osm>"#;
+
+    fn document() -> OsmDocument {
+        OsmDocument::from_xml(DOCUMENT).expect("Unable to deserialize the OSM data")
+    }
+
+    #[test]
+    fn test_parse_relations() {
+        let osm = document();
+        assert_eq!(osm.relations.len(), 2);
+
+        let restriction = &osm.relations[1];
+        assert_eq!(restriction.members.len(), 3);
+        assert_eq!(restriction.members[1].member_type, OsmMemberType::Node);
+        assert_eq!(restriction.members[1].member_ref, 2);
+        assert_eq!(restriction.role_of(OsmMemberType::Way, 11), Some("to"));
+    }
+
+    #[test]
+    fn test_relations_of_way() {
+        let osm = document();
+        let way = &osm.ways[0];
+
+        assert_eq!(way.relations(&osm).len(), 2);
+
+        let routes = way.routes(&osm);
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].label(), "bus 5");
+
+        assert!(osm.ways[1].routes(&osm).is_empty());
+    }
+
+    #[test]
+    fn test_indexed_lookup() {
+        let osm = document();
+
+        assert_eq!(osm.node(3).map(|node| node.lat), Some(60.41));
+        assert!(osm.node(4).is_none());
+        assert_eq!(osm.way(11).map(|way| way.nds.len()), Some(2));
+        assert!(osm.way(12).is_none());
+
+        let points = osm.way(10).expect("Missing way").points(&osm);
+        assert_eq!(points.iter().map(|n| n.id).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let result = OsmDocument::from_xml(&DOCUMENT[..DOCUMENT.len() / 2]);
+        assert!(matches!(result, Err(OsmError::Syntax(_))));
+    }
+
+    #[test]
+    fn test_missing_node() {
+        let xml = DOCUMENT.replace(r#"<node id="3" lat="60.41" lon="5.34"/>"#, "");
+        let result = OsmDocument::from_xml(&xml);
+        assert!(matches!(
+            result,
+            Err(OsmError::MissingNode { way: 11, node: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_cut_extract() {
+        let xml = DOCUMENT
+            .replace(r#"<node id="3" lat="60.41" lon="5.34"/>"#, "")
+            .replace(
+                "  <relation id=\"100\">",
+                r#"  <way id="12">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="4"/>
+    <tag k="highway" v="service"/>
+  </way>
+  <relation id="100">"#,
+            );
+
+        let osm = OsmDocument::parse_extract(&xml).expect("Unable to read the extract");
+        assert_eq!(
+            osm.ways.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![10, 12]
+        );
+        assert_eq!(osm.way(12).expect("Missing way").nds.len(), 2);
+        assert!(osm.way(11).is_none());
+    }
+
+    #[test]
+    fn test_overpass_runtime_error() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="Overpass API">
+  <remark> runtime error: Query timed out in "query" at line 1 after 25 seconds. </remark>
+</osm>"#;
+        let result = OsmDocument::from_xml(xml);
+        assert!(
+            matches!(result, Err(OsmError::Overpass(remark)) if remark.starts_with("runtime error: Query timed out"))
+        );
+    }
+
+    #[test]
+    fn test_empty_result() {
+        let xml = r#"<osm version="0.6" generator="Overpass API"></osm>"#;
+        assert!(matches!(OsmDocument::from_xml(xml), Err(OsmError::Empty)));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut osm = document();
+
+        let chunk = OsmDocument::from_xml(
+            r#"<osm>
+  <node id="3" lat="60.42" lon="5.35"/>
+  <node id="4" lat="60.43" lon="5.36">
+    <tag k="amenity" v="bench"/>
+  </node>
+  <way id="11" version="2">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="path"/>
+  </way>
+</osm>"#,
+        )
+        .expect("Unable to deserialize the OSM data");
+
+        osm.merge(chunk);
+
+        assert_eq!(osm.nodes.len(), 4);
+        assert_eq!(osm.ways.len(), 2);
+        assert_eq!(osm.node(3).map(|node| node.lat), Some(60.42));
+        assert_eq!(
+            osm.node(4).map(|node| node.tags[0].v.as_str()),
+            Some("bench")
+        );
+
+        let way = osm.way(11).expect("Missing way");
+        assert_eq!(way.version, Some(2));
+        assert_eq!(way.tags[0].v, "path");
+
+        let stale_chunk = OsmDocument::from_xml(
+            r#"<osm>
+  <node id="3" lat="60.41" lon="5.34"/>
+  <node id="4" lat="60.43" lon="5.36"/>
+  <way id="11" version="1">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="footway"/>
+  </way>
+</osm>"#,
+        )
+        .expect("Unable to deserialize the OSM data");
+
+        osm.merge(stale_chunk);
+
+        assert_eq!(osm.way(11).map(|way| way.version), Some(Some(2)));
+    }
+
+    #[test]
+    fn test_evict() {
+        let mut osm = document();
+        let center = Coord {
+            lat: 60.39,
+            lon: 5.32,
+        };
+
+        osm.evict(&center, 500.0);
+
+        assert_eq!(osm.ways.iter().map(|w| w.id).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(
+            osm.nodes.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(osm.relations.len(), 2);
+        assert!(osm.way(11).is_none());
+    }
+
+    #[test]
+    fn test_evict_keeps_crossing_way() {
+        let mut osm = OsmDocument::from_xml(
+            r#"<osm>
+  <node id="1" lat="60.39" lon="5.30"/>
+  <node id="2" lat="60.39" lon="5.34"/>
+  <node id="3" lat="60.42" lon="5.30"/>
+  <node id="4" lat="60.42" lon="5.34"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/></way>
+  <way id="11"><nd ref="3"/><nd ref="4"/></way>
+</osm>"#,
+        )
+        .expect("Unable to deserialize the OSM data");
+        let center = Coord {
+            lat: 60.39,
+            lon: 5.32,
+        };
+
+        osm.evict(&center, 500.0);
+
+        assert_eq!(osm.ways.iter().map(|w| w.id).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(
+            osm.nodes.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_retain_ways() {
+        let mut osm = document();
+
+        osm.retain_ways(|way| way.tag("highway") == Some("footway"));
+
+        assert_eq!(osm.ways.iter().map(|w| w.id).collect::<Vec<_>>(), vec![11]);
+        assert_eq!(
+            osm.nodes.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(
+            osm.relations.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![101]
+        );
+    }
+
+    static JSON_DOCUMENT: &str = r#"{
+  "version": 0.6,
+  "generator": "Overpass API",
+  "elements": [
+    {"type": "node", "id": 1, "lat": 60.39, "lon": 5.32},
+    {"type": "node", "id": 2, "lat": 60.40, "lon": 5.33},
+    {"type": "node", "id": 3, "lat": 60.41, "lon": 5.34},
+    {"type": "way", "id": 10, "nodes": [1, 2], "tags": {"highway": "primary"}},
+    {"type": "way", "id": 11, "nodes": [2, 3], "tags": {"highway": "footway"}},
+    {
+      "type": "relation",
+      "id": 100,
+      "members": [
+        {"type": "node", "ref": 1, "role": "stop"},
+        {"type": "way", "ref": 10, "role": ""}
+      ],
+      "tags": {"type": "route", "route": "bus", "ref": "5"}
+    },
+    {
+      "type": "relation",
+      "id": 101,
+      "members": [
+        {"type": "way", "ref": 10, "role": "from"},
+        {"type": "node", "ref": 2, "role": "via"},
+        {"type": "way", "ref": 11, "role": "to"}
+      ],
+      "tags": {"type": "restriction", "restriction": "no_left_turn"}
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_parse_json() {
+        let osm = OsmDocument::parse(JSON_DOCUMENT).expect("Unable to deserialize the OSM data");
+        let way = osm.way(10).expect("Missing way");
+
+        assert_eq!(osm.nodes.len(), 3);
+        assert_eq!(
+            way.nds.iter().map(|nd| nd.node_ref).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(way.tag("highway"), Some("primary"));
+        assert_eq!(way.routes(&osm)[0].label(), "bus 5");
+        assert_eq!(
+            osm.relations[1]
+                .tags
+                .iter()
+                .map(|tag| tag.k.as_str())
+                .collect::<Vec<_>>(),
+            vec!["type", "restriction"]
+        );
+        assert_eq!(
+            osm.way(11).expect("Missing way").relations(&osm)[0].role_of(OsmMemberType::Way, 11),
+            Some("to")
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let remark = r#"{"elements": [], "remark": "runtime error: Query timed out"}"#;
+        assert!(matches!(
+            OsmDocument::parse(remark),
+            Err(OsmError::Overpass(_))
+        ));
+
+        let missing_node = r#"{"elements": [{"type": "way", "id": 10, "nodes": [1]}]}"#;
+        assert!(matches!(
+            OsmDocument::parse(missing_node),
+            Err(OsmError::MissingNode { way: 10, node: 1 })
+        ));
+
+        let no_position = r#"{"elements": [{"type": "node", "id": 1}]}"#;
+        assert!(matches!(
+            OsmDocument::parse(no_position),
+            Err(OsmError::Syntax(_))
+        ));
+
+        assert!(matches!(
+            OsmDocument::parse(r#"{"elements": []}"#),
+            Err(OsmError::Empty)
+        ));
+    }
+
+    /// The same grid of streets in both formats.
+    fn generated_documents(size: i64) -> (String, String) {
+        let mut xml = String::from("<osm>");
+        let mut json = String::from(r#"{"elements":["#);
+
+        for row in 0..size {
+            for column in 0..size {
+                let (id, lat, lon) = (
+                    row * size + column + 1,
+                    60.0 + row as f64 * 0.001,
+                    5.0 + column as f64 * 0.001,
+                );
+                xml += &format!(
+                    r#"<node id="{}" version="1" lat="{}" lon="{}"/>"#,
+                    id, lat, lon
+                );
+                json += &format!(
+                    r#"{{"type":"node","id":{},"version":1,"lat":{},"lon":{}}},"#,
+                    id, lat, lon
+                );
+            }
+        }
+
+        for row in 0..size {
+            let id = 1_000_000 + row;
+            let nodes: Vec<i64> = (0..size).map(|column| row * size + column + 1).collect();
+
+            xml += &format!(r#"<way id="{}" version="1">"#, id);
+            for node in nodes.iter() {
+                xml += &format!(r#"<nd ref="{}"/>"#, node);
+            }
+            xml += &format!(
+                r#"<tag k="highway" v="residential"/><tag k="name" v="Street {}"/></way>"#,
+                row
+            );
+
+            json += &format!(
+                r#"{{"type":"way","id":{},"version":1,"nodes":[{}],"tags":{{"highway":"residential","name":"Street {}"}}}}"#,
+                id,
+                nodes
+                    .iter()
+                    .map(|node| node.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                row
+            );
+            json += if row + 1 < size { "," } else { "" };
+        }
+
+        xml += "</osm>";
+        json += "]}";
+        (xml, json)
+    }
+
+    #[test]
+    fn bench_parse_xml_and_json() {
+        use crate::counting_allocator::count_allocations;
+        use std::time::Instant;
+
+        let (xml, json) = generated_documents(100);
+
+        let measure = |parse: fn(&str) -> Result<OsmDocument, OsmError>, data: &str| {
+            let start = Instant::now();
+            let (osm, allocations) = count_allocations(|| parse(data).expect("Unable to parse"));
+            let elapsed = start.elapsed();
+            (osm, allocations, elapsed)
+        };
+
+        let (from_xml, xml_allocations, xml_time) = measure(OsmDocument::from_xml, &xml);
+        let (from_json, json_allocations, json_time) = measure(OsmDocument::from_json, &json);
+
+        println!(
+            "XML: {} bytes parsed in {:?}, with {:?}",
+            xml.len(),
+            xml_time,
+            xml_allocations
+        );
+        println!(
+            "JSON: {} bytes parsed in {:?}, with {:?}",
+            json.len(),
+            json_time,
+            json_allocations
+        );
+
+        // Timings vary too much to compare, but allocations don't
+        assert!(json_allocations.count < xml_allocations.count);
+        assert_eq!(from_xml.nodes.len(), from_json.nodes.len());
+        assert_eq!(from_xml.ways.len(), from_json.ways.len());
+        assert_eq!(
+            from_xml.way(1_000_042).map(|way| way.label()),
+            Some("Street 42".into())
+        );
+        assert_eq!(
+            from_json.way(1_000_042).map(|way| way.label()),
+            Some("Street 42".into())
+        );
+    }
+
+    static NOTES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="OpenStreetMap server">
+  <note lon="5.32" lat="60.39">
+    <id>1001</id>
+    <url>https://api.openstreetmap.org/api/0.6/notes/1001</url>
+    <date_created>2022-01-01 12:00:00 UTC</date_created>
+    <status>open</status>
+    <comments>
+      <comment>
+        <date>2022-01-01 12:00:00 UTC</date>
+        <action>opened</action>
+        <text>Bridge is missing</text>
+        <html>&lt;p&gt;Bridge is missing&lt;/p&gt;</html>
+      </comment>
+      <comment>
+        <date>2022-01-03 08:00:00 UTC</date>
+        <uid>42</uid>
+        <user>surveyor</user>
+        <action>commented</action>
+        <text>Still missing</text>
+        <html>&lt;p&gt;Still missing&lt;/p&gt;</html>
+      </comment>
+    </comments>
+  </note>
+  <note lon="5.33" lat="60.40">
+    <id>1002</id>
+    <date_created>2021-06-01 00:00:00 UTC</date_created>
+    <status>closed</status>
+    <date_closed>2021-06-02 00:00:00 UTC</date_closed>
+    <comments>
+      <comment>
+        <date>2021-06-01 00:00:00 UTC</date>
+        <action>opened</action>
+        <text></text>
+      </comment>
+    </comments>
+  </note>
+</osm>"#;
+
+    fn tag(k: &str, v: &str) -> OsmTag {
+        OsmTag {
+            k: k.into(),
+            v: v.into(),
+        }
+    }
+
+    fn change() -> OsmChange {
+        let mut change = OsmChange::default();
+
+        change.create_node(OsmNode {
+            id: 0,
+            version: None,
+            lat: 60.39,
+            lon: 5.32,
+            tags: vec![
+                tag("amenity", "bench"),
+                tag("name", "Ola & Kari's \"bench\""),
+            ],
+        });
+
+        change.modify_way(OsmWay {
+            id: 10,
+            version: Some(3),
+            nds: vec![OsmNd { node_ref: 1 }, OsmNd { node_ref: 2 }],
+            tags: vec![tag("highway", "residential")],
+        });
+
+        change
+    }
+
+    static CHANGE: &str = r#"<osmChange version="0.6" generator="Surway">
+  <create>
+    <node id="-1" lat="60.39" lon="5.32">
+      <tag k="amenity" v="bench"/>
+      <tag k="name" v="Ola &amp; Kari&apos;s &quot;bench&quot;"/>
+    </node>
+  </create>
+  <modify>
+    <way id="10" version="3">
+      <nd ref="1"/>
+      <nd ref="2"/>
+      <tag k="highway" v="residential"/>
+    </way>
+  </modify>
+</osmChange>
+"#;
+
+    #[test]
+    fn test_osm_change_xml() {
+        let change = change();
+
+        assert_eq!(change.to_xml(None), CHANGE);
+        assert!(change
+            .to_xml(Some(42))
+            .contains(r#"<way id="10" version="3" changeset="42">"#));
+        assert_eq!(
+            OsmChange::default().to_xml(None),
+            "<osmChange version=\"0.6\" generator=\"Surway\">\n</osmChange>\n"
+        );
+    }
+
+    #[test]
+    fn test_osm_change_round_trip() {
+        let change = change();
+        let read = OsmChange::from_xml(&change.to_xml(None)).expect("Unable to read osmChange");
+        assert_eq!(read, change);
+
+        // As saved by JOSM, with a new way using a new node
+        let josm = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osmChange version="0.6" generator="JOSM">
+<create>
+  <node id='-5' changeset='-1' lat='60.4' lon='5.33' />
+  <way id='-6' changeset='-1'>
+    <nd ref='-5' />
+    <nd ref='1' />
+    <tag k='highway' v='footway' />
+  </way>
+</create>
+<modify>
+  <way id='10' version='4' changeset='-1'>
+    <nd ref='1' />
+    <nd ref='2' />
+    <tag k='highway' v='residential' />
+    <tag k='surface' v='asphalt' />
+  </way>
+</modify>
+</osmChange>"#;
+
+        let mut merged = change;
+        merged.merge(OsmChange::from_xml(josm).expect("Unable to read osmChange"));
+
+        assert_eq!(merged.len(), 4);
+        assert_eq!(merged.create.nodes[1].id, -2);
+        assert_eq!(merged.create.ways[0].id, -3);
+        assert_eq!(merged.create.ways[0].nds[0].node_ref, -2);
+        assert_eq!(merged.modify.ways.len(), 1);
+        assert_eq!(merged.modify.ways[0].version, Some(4));
+
+        let read = OsmChange::from_xml(&merged.to_xml(None)).expect("Unable to read osmChange");
+        assert_eq!(read, merged);
+    }
+
+    #[test]
+    fn test_unsupported_osm_change() {
+        let deleting = r#"<osmChange version="0.6">
+  <delete>
+    <node id="1" version="2" lat="60.39" lon="5.32"/>
+  </delete>
+</osmChange>"#;
+
+        assert!(matches!(
+            OsmChange::from_xml(deleting),
+            Err(OsmError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_changeset_xml() {
+        let tags = [tag("created_by", "Surway"), tag("comment", "Add <bench>")];
+
+        assert_eq!(
+            changeset_xml(&tags),
+            r#"<osm>
+  <changeset>
+    <tag k="created_by" v="Surway"/>
+    <tag k="comment" v="Add &lt;bench&gt;"/>
+  </changeset>
+</osm>
+"#
+        );
+    }
+
+    #[test]
+    fn test_apply_upload() {
+        let diff = DiffResult::from_xml(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<diffResult version="0.6" generator="OpenStreetMap server">
+  <node old_id="-1" new_id="9001" new_version="1"/>
+  <way old_id="10" new_id="10" new_version="4"/>
+</diffResult>"#,
+        )
+        .expect("Unable to deserialize the diff result");
+
+        let change = change();
+        let mut osm = document();
+        osm.merge(change.to_document());
+        assert_eq!(osm.nodes.len(), 4);
+
+        // A POI saved for later, which isn't part of the upload
+        let saved = OsmChange::new_node(osm.nodes[0].clone(), &change);
+        assert_eq!(saved.create.nodes[0].id, -2);
+        osm.merge(saved.to_document());
+
+        osm.apply_upload(&change, &diff);
+
+        assert_eq!(osm.nodes.len(), 5);
+        assert!(osm.node(-1).is_none());
+        assert!(osm.node(-2).is_some());
+        assert_eq!(osm.node(9001).and_then(|node| node.version), Some(1));
+        assert_eq!(osm.way(10).and_then(|way| way.version), Some(4));
+
+        let mut pending = change.clone();
+        pending.create_node(osm.nodes[0].clone());
+        pending.remove_uploaded(&change);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending.create.nodes[0].id, -2);
+    }
+
+    #[test]
+    fn test_parse_notes() {
+        let notes = OsmNote::list_from_xml(NOTES).expect("Unable to deserialize the OSM notes");
+
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].id, 1001);
+        assert_eq!(notes[0].status, OsmNoteStatus::Open);
+        assert_eq!(notes[0].comments()[0].text, "Bridge is missing");
+        assert_eq!(notes[0].comments().len(), 2);
+        assert_eq!(notes[0].comments()[0].user, None);
+        assert_eq!(notes[0].comments()[1].user.as_deref(), Some("surveyor"));
+        assert_eq!(notes[1].status, OsmNoteStatus::Closed);
+        assert_eq!(
+            notes[1].date_closed.as_deref(),
+            Some("2021-06-02 00:00:00 UTC")
+        );
+        assert_eq!(notes[1].comments()[0].text, "");
+    }
+
+    #[test]
+    fn test_note_age() {
+        let notes = OsmNote::list_from_xml(NOTES).expect("Unable to deserialize the OSM notes");
+        let created = 1641038400000.0;
+
+        assert_eq!(parse_osm_time(&notes[0].date_created), Some(created));
+        assert_eq!(notes[0].age(created + 3600000.0), "today");
+        assert_eq!(notes[0].age(created + 86400000.0), "1 day ago");
+        assert_eq!(notes[0].age(created + 3.0 * 86400000.0), "3 days ago");
+        assert_eq!(notes[0].age(created + 90.0 * 86400000.0), "3 months ago");
+        assert_eq!(notes[0].age(created + 800.0 * 86400000.0), "2 years ago");
+    }
 }
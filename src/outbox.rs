@@ -0,0 +1,266 @@
+use crate::{
+    model::{NoteId, OsmNoteAction},
+    osm::OsmId,
+};
+use serde::{Deserialize, Serialize};
+
+const FIRST_RETRY_DELAY: f64 = 5000.0;
+const MAX_RETRY_DELAY: f64 = 10.0 * 60.0 * 1000.0;
+
+/// Something waiting to be sent to OSM.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum OutboxItem {
+    Note(NoteId),
+    NoteAction(OsmNoteAction),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum UploadState {
+    Pending,
+    Uploading,
+    Failed(String),
+    Uploaded(OsmId),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OutboxEntry {
+    pub id: u32,
+    pub item: OutboxItem,
+    pub state: UploadState,
+    pub attempts: u32,
+    /// When to try again. Failures that won't go away by themselves aren't retried automatically.
+    pub retry_at: Option<f64>,
+}
+
+/// Uploads waiting for a connection. Entries are sent one at a time, in the order they were added.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Outbox {
+    entries: Vec<OutboxEntry>,
+    next_id: u32,
+}
+
+impl Outbox {
+    pub fn push(&mut self, item: OutboxItem, now: f64) {
+        if let OutboxItem::Note(note_id) = &item {
+            if let Some(entry) = self.note_entry_mut(*note_id) {
+                if matches!(entry.state, UploadState::Failed(_)) {
+                    entry.state = UploadState::Pending;
+                    entry.retry_at = Some(now);
+                }
+                return;
+            }
+        }
+
+        self.entries.push(OutboxEntry {
+            id: self.next_id,
+            item,
+            state: UploadState::Pending,
+            attempts: 0,
+            retry_at: Some(now),
+        });
+
+        self.next_id += 1;
+    }
+
+    /// Picks the next entry that is due, and marks it as uploading.
+    pub fn start_next(&mut self, now: f64) -> Option<OutboxEntry> {
+        if self.is_uploading() {
+            return None;
+        }
+
+        let entry = self.entries.iter_mut().find(|entry| {
+            entry.state != UploadState::Uploading
+                && !matches!(entry.state, UploadState::Uploaded(_))
+                && entry.retry_at.map_or(false, |retry_at| retry_at <= now)
+        })?;
+
+        entry.state = UploadState::Uploading;
+        Some(entry.clone())
+    }
+
+    /// Marks an entry as uploaded, and returns what it was.
+    pub fn succeeded(&mut self, id: u32, osm_note_id: OsmId) -> Option<OutboxItem> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+
+        match self.entries[index].item {
+            OutboxItem::Note(_) => {
+                let entry = &mut self.entries[index];
+                entry.state = UploadState::Uploaded(osm_note_id);
+                entry.retry_at = None;
+                Some(entry.item.clone())
+            }
+            // There is nothing more to show for an action once it is done
+            OutboxItem::NoteAction(_) => Some(self.entries.remove(index).item),
+        }
+    }
+
+    pub fn failed(&mut self, id: u32, reason: String, retry: bool, now: f64) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) {
+            entry.attempts += 1;
+            entry.state = UploadState::Failed(reason);
+            entry.retry_at = if retry {
+                Some(now + retry_delay(entry.attempts))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Makes everything that hasn't been uploaded due right away, including failures that
+    /// weren't going to be retried automatically.
+    pub fn retry_all(&mut self, now: f64) {
+        for entry in self.entries.iter_mut() {
+            if matches!(entry.state, UploadState::Pending | UploadState::Failed(_)) {
+                entry.retry_at = Some(now);
+            }
+        }
+    }
+
+    /// An upload can't have survived a reload, so it will have to be done again.
+    pub fn reset_interrupted(&mut self) {
+        for entry in self.entries.iter_mut() {
+            if entry.state == UploadState::Uploading {
+                entry.state = UploadState::Pending;
+            }
+        }
+    }
+
+    /// The earliest time an entry waiting for a retry is due. Nothing is due while uploading.
+    pub fn next_retry_at(&self) -> Option<f64> {
+        if self.is_uploading() {
+            return None;
+        }
+
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.state, UploadState::Pending | UploadState::Failed(_)))
+            .filter_map(|entry| entry.retry_at)
+            .min_by(|a, b| a.partial_cmp(b).expect("Could not compare times"))
+    }
+
+    pub fn remove_note(&mut self, note_id: NoteId) {
+        self.entries
+            .retain(|entry| !matches!(entry.item, OutboxItem::Note(id) if id == note_id));
+    }
+
+    pub fn note_state(&self, note_id: NoteId) -> Option<&UploadState> {
+        self.entries
+            .iter()
+            .find(|entry| matches!(entry.item, OutboxItem::Note(id) if id == note_id))
+            .map(|entry| &entry.state)
+    }
+
+    pub fn note_actions(&self, osm_note_id: OsmId) -> Vec<(&OsmNoteAction, &UploadState)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.item {
+                OutboxItem::NoteAction(action) if action.note_id == osm_note_id => {
+                    Some((action, &entry.state))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn is_uploading(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.state == UploadState::Uploading)
+    }
+
+    fn note_entry_mut(&mut self, note_id: NoteId) -> Option<&mut OutboxEntry> {
+        self.entries
+            .iter_mut()
+            .find(|entry| matches!(entry.item, OutboxItem::Note(id) if id == note_id))
+    }
+}
+
+/// Doubles the delay for every failed attempt, up to a limit.
+fn retry_delay(attempts: u32) -> f64 {
+    (FIRST_RETRY_DELAY * 2f64.powi(attempts.saturating_sub(1) as i32)).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::OsmNoteActionKind;
+
+    fn action(note_id: OsmId) -> OutboxItem {
+        OutboxItem::NoteAction(OsmNoteAction {
+            note_id,
+            kind: OsmNoteActionKind::Close,
+            text: "".into(),
+        })
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 5000.0);
+        assert_eq!(retry_delay(2), 10000.0);
+        assert_eq!(retry_delay(4), 40000.0);
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_uploads_one_at_a_time_in_order() {
+        let mut outbox = Outbox::default();
+        outbox.push(OutboxItem::Note(NoteId(1)), 0.0);
+        outbox.push(action(42), 0.0);
+
+        let first = outbox.start_next(0.0).expect("Nothing to upload");
+        assert!(matches!(first.item, OutboxItem::Note(_)));
+        assert!(outbox.start_next(0.0).is_none());
+        assert_eq!(outbox.next_retry_at(), None);
+
+        outbox.succeeded(first.id, 1001);
+        assert_eq!(
+            outbox.note_state(NoteId(1)),
+            Some(&UploadState::Uploaded(1001))
+        );
+
+        let second = outbox.start_next(0.0).expect("Nothing to upload");
+        assert_eq!(outbox.note_actions(42).len(), 1);
+        outbox.succeeded(second.id, 42);
+        assert!(outbox.note_actions(42).is_empty());
+        assert!(outbox.start_next(0.0).is_none());
+    }
+
+    #[test]
+    fn test_backoff_after_failure() {
+        let mut outbox = Outbox::default();
+        outbox.push(OutboxItem::Note(NoteId(1)), 0.0);
+
+        let entry = outbox.start_next(0.0).expect("Nothing to upload");
+        outbox.failed(entry.id, "No connection".into(), true, 1000.0);
+
+        assert_eq!(
+            outbox.note_state(NoteId(1)),
+            Some(&UploadState::Failed("No connection".into()))
+        );
+        assert_eq!(outbox.next_retry_at(), Some(6000.0));
+        assert!(outbox.start_next(5999.0).is_none());
+
+        let entry = outbox.start_next(6000.0).expect("Nothing to upload");
+        outbox.failed(entry.id, "No connection".into(), true, 6000.0);
+        assert_eq!(outbox.next_retry_at(), Some(16000.0));
+
+        outbox.retry_all(7000.0);
+        assert!(outbox.start_next(7000.0).is_some());
+    }
+
+    #[test]
+    fn test_permanent_failure() {
+        let mut outbox = Outbox::default();
+        outbox.push(OutboxItem::Note(NoteId(1)), 0.0);
+
+        let entry = outbox.start_next(0.0).expect("Nothing to upload");
+        outbox.failed(entry.id, "400 Bad Request".into(), false, 0.0);
+
+        assert_eq!(outbox.next_retry_at(), None);
+        assert!(outbox.start_next(1e12).is_none());
+
+        outbox.push(OutboxItem::Note(NoteId(1)), 10.0);
+        assert_eq!(outbox.entries.len(), 1);
+        assert!(outbox.start_next(10.0).is_some());
+    }
+}
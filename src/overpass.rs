@@ -0,0 +1,461 @@
+use crate::osm::OsmTag;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const FOOTWAY_VALUES: [&str; 7] = [
+    "footway",
+    "path",
+    "pedestrian",
+    "steps",
+    "cycleway",
+    "bridleway",
+    "track",
+];
+
+const POI_KEYS: [&str; 6] = ["amenity", "shop", "tourism", "leisure", "craft", "office"];
+
+const CUSTOM_COLOR: &str = "#8e44ad";
+
+/// A kind of features a surveyor may be interested in.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum FeaturePreset {
+    Highways,
+    Footways,
+    Buildings,
+    Pois,
+    Landuse,
+}
+
+impl FeaturePreset {
+    pub const ALL: [FeaturePreset; 5] = [
+        FeaturePreset::Highways,
+        FeaturePreset::Footways,
+        FeaturePreset::Buildings,
+        FeaturePreset::Pois,
+        FeaturePreset::Landuse,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FeaturePreset::Highways => "Highways",
+            FeaturePreset::Footways => "Footways and paths only",
+            FeaturePreset::Buildings => "Buildings",
+            FeaturePreset::Pois => "Points of interest",
+            FeaturePreset::Landuse => "Landuse",
+        }
+    }
+
+    pub fn color(&self) -> &'static str {
+        match self {
+            FeaturePreset::Highways => "green",
+            FeaturePreset::Footways => "#a0522d",
+            FeaturePreset::Buildings => "#c0392b",
+            FeaturePreset::Pois => "#e67e22",
+            FeaturePreset::Landuse => "#7f8c8d",
+        }
+    }
+
+    fn statements(&self) -> Vec<String> {
+        match self {
+            FeaturePreset::Highways => vec!["way[\"highway\"]".into()],
+            FeaturePreset::Footways => vec![format!(
+                "way[\"highway\"~\"^({})$\"]",
+                FOOTWAY_VALUES.join("|")
+            )],
+            FeaturePreset::Buildings => vec!["way[\"building\"]".into()],
+            FeaturePreset::Pois => POI_KEYS
+                .iter()
+                .map(|key| format!("node[\"{}\"]", key))
+                .collect(),
+            FeaturePreset::Landuse => vec!["way[\"landuse\"]".into()],
+        }
+    }
+
+    fn matches(&self, tags: &[OsmTag]) -> bool {
+        match self {
+            FeaturePreset::Highways => tag(tags, "highway").is_some(),
+            FeaturePreset::Footways => {
+                tag(tags, "highway").map_or(false, |value| FOOTWAY_VALUES.contains(&value))
+            }
+            FeaturePreset::Buildings => tag(tags, "building").is_some(),
+            FeaturePreset::Pois => POI_KEYS.iter().any(|key| tag(tags, key).is_some()),
+            FeaturePreset::Landuse => tag(tags, "landuse").is_some(),
+        }
+    }
+}
+
+/// A user-supplied tag filter, like `waterway`, `power=line`, `access!=private` or
+/// `railway~tram|light_rail`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagFilter {
+    key: String,
+    condition: TagCondition,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TagCondition {
+    Exists,
+    Equals(String),
+    NotEquals(String),
+    Matches(String),
+}
+
+impl FromStr for TagFilter {
+    type Err = String;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let split = |separator: &str| {
+            filter
+                .split_once(separator)
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        };
+
+        let (key, condition) = if let Some((key, value)) = split("!=") {
+            (key, TagCondition::NotEquals(value))
+        } else if let Some((key, value)) = split("=") {
+            (key, TagCondition::Equals(value))
+        } else if let Some((key, value)) = split("~") {
+            (key, TagCondition::Matches(value))
+        } else {
+            (filter.trim().to_string(), TagCondition::Exists)
+        };
+
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("\"{}\" is not a valid tag filter", filter.trim()));
+        }
+
+        Ok(TagFilter { key, condition })
+    }
+}
+
+impl TagFilter {
+    fn to_ql(&self) -> String {
+        let key = quote(&self.key);
+
+        match &self.condition {
+            TagCondition::Exists => format!("[{}]", key),
+            TagCondition::Equals(value) => format!("[{}={}]", key, quote(value)),
+            TagCondition::NotEquals(value) => format!("[{}!={}]", key, quote(value)),
+            TagCondition::Matches(pattern) => format!("[{}~{}]", key, quote(pattern)),
+        }
+    }
+
+    /// Whether the tags pass this filter. Regular expressions are left to Overpass, so for those
+    /// only the key is checked.
+    fn matches(&self, tags: &[OsmTag]) -> bool {
+        match (&self.condition, tag(tags, &self.key)) {
+            (TagCondition::NotEquals(value), Some(actual)) => actual != value,
+            (TagCondition::NotEquals(_), None) => true,
+            (_, None) => false,
+            (TagCondition::Equals(value), Some(actual)) => actual == value,
+            (TagCondition::Exists | TagCondition::Matches(_), Some(_)) => true,
+        }
+    }
+}
+
+/// A named selection of features to download and show.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct QueryProfile {
+    pub name: String,
+    pub presets: Vec<FeaturePreset>,
+    #[serde(default)]
+    pub custom_filters: Vec<String>,
+}
+
+impl Default for QueryProfile {
+    fn default() -> Self {
+        QueryProfile {
+            name: "Highways".into(),
+            presets: vec![FeaturePreset::Highways],
+            custom_filters: vec![],
+        }
+    }
+}
+
+impl QueryProfile {
+    /// The valid custom filters, along with messages for the ones that aren't.
+    pub fn tag_filters(&self) -> (Vec<TagFilter>, Vec<String>) {
+        let mut filters = vec![];
+        let mut errors = vec![];
+
+        for filter in self.custom_filters.iter().filter(|f| !f.trim().is_empty()) {
+            match filter.parse() {
+                Ok(filter) => filters.push(filter),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        (filters, errors)
+    }
+
+    /// Composes an Overpass QL query for the area given by the `bbox` parameter. Ways come with
+    /// their nodes and the relations they are part of. The selected features are kept in a named
+    /// set, since each statement in a union reads the result of the one before it.
+    pub fn query(&self) -> String {
+        let mut statements: Vec<String> = self
+            .presets
+            .iter()
+            .flat_map(|preset| preset.statements())
+            .collect();
+
+        for filter in self.tag_filters().0 {
+            statements.push(format!("way{}", filter.to_ql()));
+            statements.push(format!("node{}", filter.to_ql()));
+        }
+
+        if statements.is_empty() {
+            statements = FeaturePreset::Highways.statements();
+        }
+
+        let union: String = statements
+            .iter()
+            .map(|statement| format!("{};", statement))
+            .collect();
+
+        format!(
+            "[bbox];({})->.selected;(.selected;.selected>;rel(bw.selected););out meta;",
+            union
+        )
+    }
+
+    /// The color to draw a way with, or `None` if it isn't among the selected features.
+    pub fn way_color(&self, tags: &[OsmTag]) -> Option<&'static str> {
+        // Check the more specific presets first
+        const PRIORITY: [FeaturePreset; 5] = [
+            FeaturePreset::Footways,
+            FeaturePreset::Highways,
+            FeaturePreset::Buildings,
+            FeaturePreset::Landuse,
+            FeaturePreset::Pois,
+        ];
+
+        PRIORITY
+            .iter()
+            .find(|preset| self.presets.contains(preset) && preset.matches(tags))
+            .map(|preset| preset.color())
+            .or_else(|| self.custom_color(tags))
+    }
+
+    /// The color to draw a node with, or `None` if it shouldn't be drawn. Nodes that are only
+    /// part of ways, like crossings, are left out.
+    pub fn node_color(&self, tags: &[OsmTag]) -> Option<&'static str> {
+        if tags.is_empty() {
+            return None;
+        }
+
+        let pois = FeaturePreset::Pois;
+
+        if self.presets.contains(&pois) && pois.matches(tags) {
+            Some(pois.color())
+        } else {
+            self.custom_color(tags)
+        }
+    }
+
+    fn custom_color(&self, tags: &[OsmTag]) -> Option<&'static str> {
+        self.tag_filters()
+            .0
+            .iter()
+            .any(|filter| filter.matches(tags))
+            .then_some(CUSTOM_COLOR)
+    }
+}
+
+/// The saved profiles, and which one is in use. There is always at least one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QueryProfiles {
+    profiles: Vec<QueryProfile>,
+    active: usize,
+}
+
+impl Default for QueryProfiles {
+    fn default() -> Self {
+        QueryProfiles {
+            profiles: vec![QueryProfile::default()],
+            active: 0,
+        }
+    }
+}
+
+impl QueryProfiles {
+    pub fn all(&self) -> &[QueryProfile] {
+        &self.profiles
+    }
+
+    pub fn active(&self) -> &QueryProfile {
+        &self.profiles[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut QueryProfile {
+        &mut self.profiles[self.active]
+    }
+
+    pub fn select(&mut self, name: &str) {
+        if let Some(index) = self.profiles.iter().position(|p| p.name == name) {
+            self.active = index;
+        }
+    }
+
+    /// Saves a copy of the active profile under a new name, and switches to it. An existing
+    /// profile with the same name is replaced.
+    pub fn save_as(&mut self, name: &str) {
+        let name = name.trim();
+
+        if name.is_empty() {
+            return;
+        }
+
+        let profile = QueryProfile {
+            name: name.into(),
+            ..self.active().clone()
+        };
+
+        match self.profiles.iter().position(|p| p.name == name) {
+            Some(index) => {
+                self.profiles[index] = profile;
+                self.active = index;
+            }
+            None => {
+                self.profiles.push(profile);
+                self.active = self.profiles.len() - 1;
+            }
+        }
+    }
+
+    /// Deletes the active profile, unless it is the last one.
+    pub fn delete_active(&mut self) {
+        if self.profiles.len() > 1 {
+            self.profiles.remove(self.active);
+            self.active = self.active.saturating_sub(1);
+        }
+    }
+}
+
+fn tag<'a>(tags: &'a [OsmTag], key: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|tag| tag.k == key)
+        .map(|tag| tag.v.as_str())
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<OsmTag> {
+        pairs
+            .iter()
+            .map(|(k, v)| OsmTag {
+                k: k.to_string(),
+                v: v.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_default_query() {
+        assert_eq!(
+            QueryProfile::default().query(),
+            "[bbox];(way[\"highway\"];)->.selected;(.selected;.selected>;rel(bw.selected););out meta;"
+        );
+    }
+
+    #[test]
+    fn test_query_with_presets_and_filters() {
+        let profile = QueryProfile {
+            name: "Survey".into(),
+            presets: vec![FeaturePreset::Buildings, FeaturePreset::Pois],
+            custom_filters: vec!["power=line".into(), "".into(), "bad key".into()],
+        };
+
+        assert_eq!(
+            profile.query(),
+            format!(
+                "[bbox];({}{}{}{})->.selected;(.selected;.selected>;rel(bw.selected););out meta;",
+                "way[\"building\"];",
+                "node[\"amenity\"];node[\"shop\"];node[\"tourism\"];node[\"leisure\"];",
+                "node[\"craft\"];node[\"office\"];",
+                "way[\"power\"=\"line\"];node[\"power\"=\"line\"];"
+            )
+        );
+        assert_eq!(
+            profile.tag_filters().1,
+            vec!["\"bad key\" is not a valid tag filter".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let filter: TagFilter = "name!=Main \"Street\"".parse().unwrap();
+        assert_eq!(filter.to_ql(), "[\"name\"!=\"Main \\\"Street\\\"\"]");
+
+        let filter: TagFilter = "railway~tram|light_rail".parse().unwrap();
+        assert_eq!(filter.to_ql(), "[\"railway\"~\"tram|light_rail\"]");
+
+        let filter: TagFilter = " waterway ".parse().unwrap();
+        assert_eq!(filter.to_ql(), "[\"waterway\"]");
+        assert!(filter.matches(&tags(&[("waterway", "stream")])));
+        assert!(!filter.matches(&tags(&[("highway", "primary")])));
+
+        assert!("=value".parse::<TagFilter>().is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut profiles = QueryProfiles::default();
+        profiles.active_mut().presets.push(FeaturePreset::Pois);
+        profiles.save_as(" Shops ");
+        profiles.active_mut().presets = vec![FeaturePreset::Buildings];
+
+        assert_eq!(profiles.all().len(), 2);
+        assert_eq!(profiles.active().name, "Shops");
+
+        profiles.select("Highways");
+        assert_eq!(
+            profiles.active().presets,
+            vec![FeaturePreset::Highways, FeaturePreset::Pois]
+        );
+
+        profiles.save_as("Shops");
+        assert_eq!(profiles.all().len(), 2);
+        assert_eq!(profiles.active().presets.len(), 2);
+
+        profiles.delete_active();
+        profiles.delete_active();
+        assert_eq!(profiles.all().len(), 1);
+        assert_eq!(profiles.active().name, "Highways");
+    }
+
+    #[test]
+    fn test_color() {
+        let profile = QueryProfile {
+            name: "All".into(),
+            presets: vec![FeaturePreset::Highways, FeaturePreset::Footways],
+            custom_filters: vec!["waterway".into()],
+        };
+
+        assert_eq!(
+            profile.way_color(&tags(&[("highway", "primary")])),
+            Some("green")
+        );
+        assert_eq!(
+            profile.way_color(&tags(&[("highway", "footway")])),
+            Some("#a0522d")
+        );
+        assert_eq!(
+            profile.way_color(&tags(&[("waterway", "river")])),
+            Some(CUSTOM_COLOR)
+        );
+        assert_eq!(profile.way_color(&tags(&[("building", "yes")])), None);
+
+        assert_eq!(profile.node_color(&tags(&[("highway", "crossing")])), None);
+        assert_eq!(profile.node_color(&tags(&[("amenity", "cafe")])), None);
+        assert_eq!(
+            profile.node_color(&tags(&[("waterway", "waterfall")])),
+            Some(CUSTOM_COLOR)
+        );
+    }
+}
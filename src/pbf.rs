@@ -0,0 +1,793 @@
+//! A reader for OSM PBF files, as described on https://wiki.openstreetmap.org/wiki/PBF_Format.
+//!
+//! Only the elements around an area of interest are kept, so a regional extract can be surveyed
+//! without holding all of it in memory. This module only depends on `miniz_oxide`, so the
+//! osm-chunk-sizer tool can include it as well.
+
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+use std::collections::HashSet;
+
+const SUPPORTED_FEATURES: [&str; 2] = ["OsmSchema-V0.6", "DenseNodes"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+impl Bounds {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+}
+
+#[derive(Debug)]
+pub struct PbfNode {
+    pub id: i64,
+    pub version: Option<u32>,
+    pub lat: f64,
+    pub lon: f64,
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct PbfWay {
+    pub id: i64,
+    pub version: Option<u32>,
+    pub refs: Vec<i64>,
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PbfMemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+#[derive(Debug)]
+pub struct PbfMember {
+    pub member_type: PbfMemberType,
+    pub id: i64,
+    pub role: String,
+}
+
+#[derive(Debug)]
+pub struct PbfRelation {
+    pub id: i64,
+    pub version: Option<u32>,
+    pub members: Vec<PbfMember>,
+    pub tags: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct PbfData {
+    pub nodes: Vec<PbfNode>,
+    pub ways: Vec<PbfWay>,
+    pub relations: Vec<PbfRelation>,
+}
+
+/// Reads the nodes within the bounds, the ways using any of them (with all their nodes), and the
+/// relations having any of those as members. Expects the file to be sorted, with nodes first, as
+/// extracts usually are.
+pub fn read(data: &[u8], bounds: &Bounds) -> Result<PbfData, String> {
+    let mut reader = ElementReader {
+        bounds: *bounds,
+        pass: Pass::Area,
+        kept_nodes: HashSet::new(),
+        kept_ways: HashSet::new(),
+        needed_nodes: HashSet::new(),
+        data: PbfData::default(),
+    };
+
+    for_each_block(data, |block| reader.read_block(block))?;
+
+    // Ways reaching out of the area need their remaining nodes too
+    reader.pass = Pass::MissingNodes;
+    for_each_block(data, |block| reader.read_block(block))?;
+
+    let mut data = reader.data;
+
+    // Extracts may have cut ways at their border
+    let node_ids: HashSet<i64> = data.nodes.iter().map(|node| node.id).collect();
+    for way in data.ways.iter_mut() {
+        way.refs.retain(|id| node_ids.contains(id));
+    }
+    data.ways.retain(|way| way.refs.len() > 1);
+
+    Ok(data)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pass {
+    Area,
+    MissingNodes,
+}
+
+struct ElementReader {
+    bounds: Bounds,
+    pass: Pass,
+    kept_nodes: HashSet<i64>,
+    kept_ways: HashSet<i64>,
+    needed_nodes: HashSet<i64>,
+    data: PbfData,
+}
+
+impl ElementReader {
+    fn read_block(&mut self, block: PrimitiveBlock) -> Result<(), String> {
+        for group in block.groups.iter() {
+            let mut fields = Fields::new(group);
+
+            while let Some((number, value)) = fields.next_field()? {
+                match (number, self.pass) {
+                    (1, _) => self.read_node(&block, value.bytes()?)?,
+                    (2, _) => self.read_dense_nodes(&block, value.bytes()?)?,
+                    (3, Pass::Area) => self.read_way(&block, value.bytes()?)?,
+                    (4, Pass::Area) => self.read_relation(&block, value.bytes()?)?,
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wants_node(&self, id: i64, lat: f64, lon: f64) -> bool {
+        match self.pass {
+            Pass::Area => self.bounds.contains(lat, lon),
+            Pass::MissingNodes => self.needed_nodes.contains(&id) && !self.kept_nodes.contains(&id),
+        }
+    }
+
+    fn keep_node(&mut self, node: PbfNode) {
+        self.kept_nodes.insert(node.id);
+        self.data.nodes.push(node);
+    }
+
+    fn read_node(&mut self, block: &PrimitiveBlock, data: &[u8]) -> Result<(), String> {
+        let (mut id, mut lat, mut lon, mut version) = (0, 0, 0, None);
+        let (mut keys, mut vals) = (vec![], vec![]);
+        let mut fields = Fields::new(data);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => id = zigzag(value.varint()?),
+                2 => keys = packed(value.bytes()?)?,
+                3 => vals = packed(value.bytes()?)?,
+                4 => version = read_version(value.bytes()?)?,
+                8 => lat = zigzag(value.varint()?),
+                9 => lon = zigzag(value.varint()?),
+                _ => (),
+            }
+        }
+
+        let (lat, lon) = block.position(lat, lon);
+
+        if self.wants_node(id, lat, lon) {
+            self.keep_node(PbfNode {
+                id,
+                version,
+                lat,
+                lon,
+                tags: block.tags(&keys, &vals)?,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_dense_nodes(&mut self, block: &PrimitiveBlock, data: &[u8]) -> Result<(), String> {
+        let (mut ids, mut lats, mut lons) = (vec![], vec![], vec![]);
+        let (mut versions, mut keys_vals) = (vec![], vec![]);
+        let mut fields = Fields::new(data);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => ids = packed(value.bytes()?)?,
+                5 => {
+                    let mut info = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = info.next_field()? {
+                        if number == 1 {
+                            versions = packed(value.bytes()?)?;
+                        }
+                    }
+                }
+                8 => lats = packed(value.bytes()?)?,
+                9 => lons = packed(value.bytes()?)?,
+                10 => keys_vals = packed(value.bytes()?)?,
+                _ => (),
+            }
+        }
+
+        if lats.len() != ids.len() || lons.len() != ids.len() {
+            return Err("Dense nodes have missing coordinates".into());
+        }
+
+        let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+        let mut keys_vals = keys_vals.into_iter();
+
+        for i in 0..ids.len() {
+            // Everything but the tags is delta coded
+            id += zigzag(ids[i]);
+            lat += zigzag(lats[i]);
+            lon += zigzag(lons[i]);
+
+            let mut tag_ids = vec![];
+            while let Some(key) = keys_vals.next().filter(|&key| key != 0) {
+                let val = keys_vals.next().ok_or("Dense node tag has no value")?;
+                tag_ids.push((key, val));
+            }
+
+            let (node_lat, node_lon) = block.position(lat, lon);
+
+            if self.wants_node(id, node_lat, node_lon) {
+                let tags = tag_ids
+                    .iter()
+                    .map(|&(key, val)| Ok((block.string(key)?, block.string(val)?)))
+                    .collect::<Result<_, String>>()?;
+
+                self.keep_node(PbfNode {
+                    id,
+                    version: versions.get(i).map(|&version| version as u32),
+                    lat: node_lat,
+                    lon: node_lon,
+                    tags,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_way(&mut self, block: &PrimitiveBlock, data: &[u8]) -> Result<(), String> {
+        let (mut id, mut version, mut refs) = (0, None, vec![]);
+        let (mut keys, mut vals) = (vec![], vec![]);
+        let mut fields = Fields::new(data);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => id = value.varint()? as i64,
+                2 => keys = packed(value.bytes()?)?,
+                3 => vals = packed(value.bytes()?)?,
+                4 => version = read_version(value.bytes()?)?,
+                8 => refs = deltas(&packed(value.bytes()?)?),
+                _ => (),
+            }
+        }
+
+        if refs.iter().any(|id| self.kept_nodes.contains(id)) {
+            self.needed_nodes.extend(refs.iter().copied());
+            self.kept_ways.insert(id);
+            self.data.ways.push(PbfWay {
+                id,
+                version,
+                refs,
+                tags: block.tags(&keys, &vals)?,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_relation(&mut self, block: &PrimitiveBlock, data: &[u8]) -> Result<(), String> {
+        let (mut id, mut version) = (0, None);
+        let (mut keys, mut vals, mut roles, mut ids, mut types) =
+            (vec![], vec![], vec![], vec![], vec![]);
+        let mut fields = Fields::new(data);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => id = value.varint()? as i64,
+                2 => keys = packed(value.bytes()?)?,
+                3 => vals = packed(value.bytes()?)?,
+                4 => version = read_version(value.bytes()?)?,
+                8 => roles = packed(value.bytes()?)?,
+                9 => ids = deltas(&packed(value.bytes()?)?),
+                10 => types = packed(value.bytes()?)?,
+                _ => (),
+            }
+        }
+
+        let members: Vec<PbfMember> = ids
+            .iter()
+            .zip(types.iter())
+            .zip(roles.iter())
+            .map(|((&id, &member_type), &role)| {
+                let member_type = match member_type {
+                    0 => PbfMemberType::Node,
+                    1 => PbfMemberType::Way,
+                    2 => PbfMemberType::Relation,
+                    _ => return Err(format!("Unknown member type {}", member_type)),
+                };
+
+                Ok(PbfMember {
+                    member_type,
+                    id,
+                    role: block.string(role)?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        let is_used = members.iter().any(|member| match member.member_type {
+            PbfMemberType::Node => self.kept_nodes.contains(&member.id),
+            PbfMemberType::Way => self.kept_ways.contains(&member.id),
+            PbfMemberType::Relation => false,
+        });
+
+        if is_used {
+            self.data.relations.push(PbfRelation {
+                id,
+                version,
+                members,
+                tags: block.tags(&keys, &vals)?,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+struct PrimitiveBlock<'a> {
+    strings: Vec<&'a [u8]>,
+    groups: Vec<&'a [u8]>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl<'a> PrimitiveBlock<'a> {
+    fn parse(data: &'a [u8]) -> Result<PrimitiveBlock<'a>, String> {
+        let mut block = PrimitiveBlock {
+            strings: vec![],
+            groups: vec![],
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+        };
+
+        let mut fields = Fields::new(data);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => {
+                    let mut strings = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = strings.next_field()? {
+                        if number == 1 {
+                            block.strings.push(value.bytes()?);
+                        }
+                    }
+                }
+                2 => block.groups.push(value.bytes()?),
+                17 => block.granularity = value.varint()? as i64,
+                19 => block.lat_offset = value.varint()? as i64,
+                20 => block.lon_offset = value.varint()? as i64,
+                _ => (),
+            }
+        }
+
+        Ok(block)
+    }
+
+    fn position(&self, lat: i64, lon: i64) -> (f64, f64) {
+        (
+            1e-9 * (self.lat_offset + self.granularity * lat) as f64,
+            1e-9 * (self.lon_offset + self.granularity * lon) as f64,
+        )
+    }
+
+    fn string(&self, index: u64) -> Result<String, String> {
+        let bytes = self
+            .strings
+            .get(index as usize)
+            .ok_or_else(|| format!("String {} is missing", index))?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())
+    }
+
+    fn tags(&self, keys: &[u64], vals: &[u64]) -> Result<Vec<(String, String)>, String> {
+        keys.iter()
+            .zip(vals.iter())
+            .map(|(&key, &val)| Ok((self.string(key)?, self.string(val)?)))
+            .collect()
+    }
+}
+
+/// Goes through the data blocks of the file, after checking that its header is one we can read.
+fn for_each_block(
+    data: &[u8],
+    mut f: impl FnMut(PrimitiveBlock) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut position = 0;
+
+    while position < data.len() {
+        let header_size = data
+            .get(position..position + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+            .ok_or("Truncated blob header size")?;
+        position += 4;
+
+        let header = data
+            .get(position..position + header_size)
+            .ok_or("Truncated blob header")?;
+        position += header_size;
+
+        let (mut blob_type, mut blob_size) = ("".to_string(), 0);
+        let mut fields = Fields::new(header);
+
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => blob_type = String::from_utf8_lossy(value.bytes()?).into(),
+                3 => blob_size = value.varint()? as usize,
+                _ => (),
+            }
+        }
+
+        let blob = data
+            .get(position..position + blob_size)
+            .ok_or("Truncated blob")?;
+        position += blob_size;
+
+        match blob_type.as_str() {
+            "OSMHeader" => check_header(&decompress(blob)?)?,
+            "OSMData" => f(PrimitiveBlock::parse(&decompress(blob)?)?)?,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn check_header(data: &[u8]) -> Result<(), String> {
+    let mut fields = Fields::new(data);
+
+    while let Some((number, value)) = fields.next_field()? {
+        if number == 4 {
+            let feature = String::from_utf8_lossy(value.bytes()?);
+
+            if !SUPPORTED_FEATURES.contains(&feature.as_ref()) {
+                return Err(format!("Unsupported PBF feature {}", feature));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn decompress(blob: &[u8]) -> Result<Vec<u8>, String> {
+    let mut fields = Fields::new(blob);
+
+    while let Some((number, value)) = fields.next_field()? {
+        match number {
+            1 => return Ok(value.bytes()?.to_vec()),
+            3 => {
+                return decompress_to_vec_zlib(value.bytes()?)
+                    .map_err(|e| format!("Unable to decompress blob: {:?}", e))
+            }
+            4..=7 => return Err("Only zlib compressed PBF files are supported".into()),
+            _ => (),
+        }
+    }
+
+    Err("Blob has no data".into())
+}
+
+fn read_version(info: &[u8]) -> Result<Option<u32>, String> {
+    let mut fields = Fields::new(info);
+
+    while let Some((number, value)) = fields.next_field()? {
+        if number == 1 {
+            return Ok(Some(value.varint()? as u32));
+        }
+    }
+
+    Ok(None)
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    fn varint(&self) -> Result<u64, String> {
+        match self {
+            Value::Varint(value) => Ok(*value),
+            _ => Err("Expected a varint".into()),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'a [u8], String> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err("Expected a length delimited field".into()),
+        }
+    }
+}
+
+/// The fields of a protocol buffer message.
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data, position: 0 }
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u32, Value<'a>)>, String> {
+        if self.position >= self.data.len() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let number = (key >> 3) as u32;
+
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => self.skip(8)?,
+            2 => {
+                let length = self.varint()? as usize;
+                let bytes = self
+                    .data
+                    .get(self.position..self.position + length)
+                    .ok_or("Truncated field")?;
+                self.position += length;
+                Value::Bytes(bytes)
+            }
+            5 => self.skip(4)?,
+            wire_type => return Err(format!("Unsupported wire type {}", wire_type)),
+        };
+
+        Ok(Some((number, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.position).ok_or("Truncated varint")?;
+            self.position += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err("Varint is too long".into())
+    }
+
+    fn skip(&mut self, length: usize) -> Result<Value<'a>, String> {
+        if self.position + length > self.data.len() {
+            return Err("Truncated field".into());
+        }
+
+        self.position += length;
+        Ok(Value::Fixed)
+    }
+}
+
+fn packed(data: &[u8]) -> Result<Vec<u64>, String> {
+    let mut fields = Fields::new(data);
+    let mut values = vec![];
+
+    while fields.position < data.len() {
+        values.push(fields.varint()?);
+    }
+
+    Ok(values)
+}
+
+fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn deltas(values: &[u64]) -> Vec<i64> {
+    values
+        .iter()
+        .scan(0, |sum, &value| {
+            *sum += zigzag(value);
+            Some(*sum)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn int_field(number: u32, value: u64, out: &mut Vec<u8>) {
+        varint((number as u64) << 3, out);
+        varint(value, out);
+    }
+
+    fn bytes_field(number: u32, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(((number as u64) << 3) | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn packed_field(number: u32, values: &[u64], out: &mut Vec<u8>) {
+        let mut bytes = vec![];
+        for &value in values {
+            varint(value, &mut bytes);
+        }
+        bytes_field(number, &bytes, out);
+    }
+
+    fn encode_zigzag(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    fn encode_deltas(values: &[i64]) -> Vec<u64> {
+        let mut previous = 0;
+        values
+            .iter()
+            .map(|&value| {
+                let delta = value - previous;
+                previous = value;
+                encode_zigzag(delta)
+            })
+            .collect()
+    }
+
+    fn blob(blob_type: &str, data: &[u8], compress: bool) -> Vec<u8> {
+        let mut blob = vec![];
+        if compress {
+            int_field(2, data.len() as u64, &mut blob);
+            bytes_field(3, &compress_to_vec_zlib(data, 6), &mut blob);
+        } else {
+            bytes_field(1, data, &mut blob);
+        }
+
+        let mut header = vec![];
+        bytes_field(1, blob_type.as_bytes(), &mut header);
+        int_field(3, blob.len() as u64, &mut header);
+
+        let mut out = (header.len() as u32).to_be_bytes().to_vec();
+        out.extend(header);
+        out.extend(blob);
+        out
+    }
+
+    /// Nodes 1 and 2 are inside the test bounds, 3 and 4 are outside. Way 11 leaves the area, and
+    /// way 12 is entirely outside it.
+    fn file() -> Vec<u8> {
+        let strings = [
+            "", "highway", "primary", "footway", "amenity", "bench", "type", "route", "stop",
+        ];
+
+        let mut string_table = vec![];
+        for string in strings {
+            bytes_field(1, string.as_bytes(), &mut string_table);
+        }
+
+        // Coordinates are in units of 100 nanodegrees, the default granularity
+        let coordinate = |degrees: f64| (degrees * 1e7).round() as i64;
+
+        let mut dense = vec![];
+        packed_field(1, &encode_deltas(&[1, 2, 3]), &mut dense);
+        let mut dense_info = vec![];
+        packed_field(1, &[1, 2, 1], &mut dense_info);
+        bytes_field(5, &dense_info, &mut dense);
+        packed_field(
+            8,
+            &encode_deltas(&[coordinate(60.39), coordinate(60.391), coordinate(60.5)]),
+            &mut dense,
+        );
+        packed_field(
+            9,
+            &encode_deltas(&[coordinate(5.32), coordinate(5.321), coordinate(5.5)]),
+            &mut dense,
+        );
+        packed_field(10, &[0, 4, 5, 0, 0], &mut dense);
+
+        let mut node = vec![];
+        int_field(1, encode_zigzag(4), &mut node);
+        int_field(8, encode_zigzag(coordinate(60.6)), &mut node);
+        int_field(9, encode_zigzag(coordinate(5.6)), &mut node);
+
+        let mut nodes_group = vec![];
+        bytes_field(2, &dense, &mut nodes_group);
+        bytes_field(1, &node, &mut nodes_group);
+
+        let mut ways_group = vec![];
+        for (id, refs, highway) in [(10, [1, 2], 2), (11, [2, 3], 3), (12, [3, 4], 3)] {
+            let mut way = vec![];
+            int_field(1, id, &mut way);
+            packed_field(2, &[1], &mut way);
+            packed_field(3, &[highway], &mut way);
+            packed_field(8, &encode_deltas(&refs), &mut way);
+            bytes_field(3, &way, &mut ways_group);
+        }
+
+        let mut relations_group = vec![];
+        for (id, member_type, member) in [(100, 1, 12), (101, 0, 1)] {
+            let mut relation = vec![];
+            int_field(1, id, &mut relation);
+            packed_field(2, &[6], &mut relation);
+            packed_field(3, &[7], &mut relation);
+            packed_field(8, &[8], &mut relation);
+            packed_field(9, &encode_deltas(&[member]), &mut relation);
+            packed_field(10, &[member_type], &mut relation);
+            bytes_field(4, &relation, &mut relations_group);
+        }
+
+        let mut block = vec![];
+        bytes_field(1, &string_table, &mut block);
+        bytes_field(2, &nodes_group, &mut block);
+        bytes_field(2, &ways_group, &mut block);
+        bytes_field(2, &relations_group, &mut block);
+
+        let mut header = vec![];
+        bytes_field(4, b"OsmSchema-V0.6", &mut header);
+        bytes_field(4, b"DenseNodes", &mut header);
+
+        let mut file = blob("OSMHeader", &header, false);
+        file.extend(blob("OSMData", &block, true));
+        file
+    }
+
+    fn bounds() -> Bounds {
+        Bounds {
+            min_lat: 60.38,
+            min_lon: 5.31,
+            max_lat: 60.4,
+            max_lon: 5.33,
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let data = read(&file(), &bounds()).expect("Unable to read PBF");
+
+        let node_ids: Vec<i64> = data.nodes.iter().map(|node| node.id).collect();
+        assert_eq!(node_ids, vec![1, 2, 3]);
+        assert!((data.nodes[1].lat - 60.391).abs() < 1e-9);
+        assert!((data.nodes[1].lon - 5.321).abs() < 1e-9);
+        assert_eq!(data.nodes[1].version, Some(2));
+        assert_eq!(data.nodes[1].tags, vec![("amenity".into(), "bench".into())]);
+
+        let way_ids: Vec<i64> = data.ways.iter().map(|way| way.id).collect();
+        assert_eq!(way_ids, vec![10, 11]);
+        assert_eq!(data.ways[1].refs, vec![2, 3]);
+        assert_eq!(
+            data.ways[1].tags,
+            vec![("highway".into(), "footway".into())]
+        );
+
+        assert_eq!(data.relations.len(), 1);
+        assert_eq!(data.relations[0].id, 101);
+        assert_eq!(data.relations[0].members[0].role, "stop");
+    }
+
+    #[test]
+    fn test_unsupported_feature() {
+        let mut header = vec![];
+        bytes_field(4, b"HistoricalInformation", &mut header);
+
+        assert_eq!(
+            read(&blob("OSMHeader", &header, false), &bounds()).unwrap_err(),
+            "Unsupported PBF feature HistoricalInformation"
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let file = file();
+        assert!(read(&file[..file.len() - 10], &bounds()).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    geo::Coord,
+    osm::{OsmNode, OsmTag},
+    tag_edit::{parse_lines, parse_tags},
+};
+
+/// The kinds of points of interest that can be added, with the tags they need.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoiPreset {
+    Bench,
+    WasteBasket,
+    BusStop,
+    FireHydrant,
+    DrinkingWater,
+    Toilets,
+    Shop,
+    Other,
+}
+
+impl PoiPreset {
+    pub const ALL: [PoiPreset; 8] = [
+        PoiPreset::Bench,
+        PoiPreset::WasteBasket,
+        PoiPreset::BusStop,
+        PoiPreset::FireHydrant,
+        PoiPreset::DrinkingWater,
+        PoiPreset::Toilets,
+        PoiPreset::Shop,
+        PoiPreset::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoiPreset::Bench => "bench",
+            PoiPreset::WasteBasket => "waste-basket",
+            PoiPreset::BusStop => "bus-stop",
+            PoiPreset::FireHydrant => "fire-hydrant",
+            PoiPreset::DrinkingWater => "drinking-water",
+            PoiPreset::Toilets => "toilets",
+            PoiPreset::Shop => "shop",
+            PoiPreset::Other => "other",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PoiPreset::Bench => "Bench",
+            PoiPreset::WasteBasket => "Waste basket",
+            PoiPreset::BusStop => "Bus stop",
+            PoiPreset::FireHydrant => "Fire hydrant",
+            PoiPreset::DrinkingWater => "Drinking water",
+            PoiPreset::Toilets => "Toilets",
+            PoiPreset::Shop => "Shop",
+            PoiPreset::Other => "Other",
+        }
+    }
+
+    pub fn parse(preset: &str) -> Option<PoiPreset> {
+        PoiPreset::ALL.into_iter().find(|p| p.as_str() == preset)
+    }
+
+    /// The tags identifying this kind of POI.
+    fn tags(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            PoiPreset::Bench => &[("amenity", "bench")],
+            PoiPreset::WasteBasket => &[("amenity", "waste_basket")],
+            PoiPreset::BusStop => &[
+                ("highway", "bus_stop"),
+                ("public_transport", "platform"),
+                ("bus", "yes"),
+            ],
+            PoiPreset::FireHydrant => &[("emergency", "fire_hydrant")],
+            PoiPreset::DrinkingWater => &[("amenity", "drinking_water")],
+            PoiPreset::Toilets => &[("amenity", "toilets")],
+            PoiPreset::Shop => &[("shop", "yes")],
+            PoiPreset::Other => &[],
+        }
+    }
+
+    /// Tags worth filling in for this kind of POI, offered with empty values.
+    fn fields(&self) -> &'static [&'static str] {
+        match self {
+            PoiPreset::Bench => &["backrest", "material"],
+            PoiPreset::WasteBasket => &[],
+            PoiPreset::BusStop => &["name", "shelter", "bench"],
+            PoiPreset::FireHydrant => &["fire_hydrant:type", "ref"],
+            PoiPreset::DrinkingWater => &["seasonal"],
+            PoiPreset::Toilets => &["fee", "wheelchair", "opening_hours"],
+            PoiPreset::Shop => &["name", "opening_hours"],
+            PoiPreset::Other => &["name"],
+        }
+    }
+}
+
+/// A point of interest being filled in before uploading. The tags are edited as text, with one
+/// `key=value` per line.
+#[derive(Clone, Debug)]
+pub struct PoiDraft {
+    pub preset: PoiPreset,
+    pub position: Coord,
+    pub tags: String,
+    pub comment: String,
+}
+
+impl PoiDraft {
+    pub fn new(preset: PoiPreset, position: Coord) -> PoiDraft {
+        let mut draft = PoiDraft {
+            preset,
+            position,
+            tags: "".into(),
+            comment: "".into(),
+        };
+
+        draft.set_preset(preset);
+        draft
+    }
+
+    /// Swaps the tags of the old preset for those of the new one. Values filled in for other keys
+    /// are kept.
+    pub fn set_preset(&mut self, preset: PoiPreset) {
+        let old_preset = self.preset;
+        let mut lines: Vec<(String, String)> = parse_lines(&self.tags)
+            .filter_map(Result::ok)
+            .filter(|(key, value)| {
+                let is_preset_tag = old_preset.tags().contains(&(key.as_str(), value.as_str()));
+                let is_empty_field =
+                    value.is_empty() && old_preset.fields().contains(&key.as_str());
+                !is_preset_tag && !is_empty_field
+            })
+            .collect();
+
+        for (i, (key, value)) in preset.tags().iter().enumerate() {
+            lines.retain(|(k, _)| k != key);
+            lines.insert(i, (key.to_string(), value.to_string()));
+        }
+
+        for field in preset.fields() {
+            if !lines.iter().any(|(key, _)| key == field) {
+                lines.push((field.to_string(), "".into()));
+            }
+        }
+
+        if self.comment.is_empty() || self.comment == default_comment(old_preset) {
+            self.comment = default_comment(preset);
+        }
+
+        self.preset = preset;
+        self.tags = lines
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect();
+    }
+
+    /// The tags to upload. Keys left without a value are skipped.
+    pub fn tags(&self) -> Result<Vec<OsmTag>, String> {
+        let tags = parse_tags(&self.tags)?;
+
+        if tags.is_empty() {
+            return Err("The point of interest needs at least one tag".into());
+        }
+
+        Ok(tags)
+    }
+
+    /// The node to add. It gets its id when added to a change.
+    pub fn to_node(&self) -> Result<OsmNode, String> {
+        Ok(OsmNode {
+            id: 0,
+            version: None,
+            lat: self.position.lat,
+            lon: self.position.lon,
+            tags: self.tags()?,
+        })
+    }
+}
+
+fn default_comment(preset: PoiPreset) -> String {
+    match preset {
+        PoiPreset::Other => "".into(),
+        _ => format!("Add {}", preset.label().to_lowercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position() -> Coord {
+        Coord {
+            lat: 60.39,
+            lon: 5.32,
+        }
+    }
+
+    #[test]
+    fn test_new_draft() {
+        let draft = PoiDraft::new(PoiPreset::BusStop, position());
+
+        assert_eq!(
+            draft.tags,
+            "highway=bus_stop\npublic_transport=platform\nbus=yes\nname=\nshelter=\nbench=\n"
+        );
+        assert_eq!(draft.comment, "Add bus stop");
+
+        let node = draft.to_node().expect("Invalid draft");
+        assert_eq!(node.lat, 60.39);
+        assert_eq!(node.tags.len(), 3);
+    }
+
+    #[test]
+    fn test_switch_preset() {
+        let mut draft = PoiDraft::new(PoiPreset::Shop, position());
+        draft.tags = "shop=yes\nname=Kiosken\nopening_hours=\n".into();
+        draft.set_preset(PoiPreset::Toilets);
+
+        assert_eq!(
+            draft.tags,
+            "amenity=toilets\nname=Kiosken\nfee=\nwheelchair=\nopening_hours=\n"
+        );
+        assert_eq!(draft.comment, "Add toilets");
+
+        draft.comment = "Toilets by the park".into();
+        draft.set_preset(PoiPreset::Bench);
+        assert_eq!(draft.comment, "Toilets by the park");
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        let mut draft = PoiDraft::new(PoiPreset::Other, position());
+        assert_eq!(
+            draft.tags(),
+            Err("The point of interest needs at least one tag".into())
+        );
+
+        draft.tags = "amenity=bench\nbench".into();
+        assert!(draft.to_node().is_err());
+    }
+}
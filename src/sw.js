@@ -0,0 +1,16 @@
+// Only here so Surway can show system notifications, which some browsers (like Chrome on Android)
+// won't show without a service worker. It doesn't cache anything.
+
+self.addEventListener("install", () => self.skipWaiting());
+
+self.addEventListener("activate", (event) => event.waitUntil(self.clients.claim()));
+
+// Bring Surway back to the front when a notification is tapped
+self.addEventListener("notificationclick", (event) => {
+  event.notification.close();
+  event.waitUntil(
+    self.clients
+      .matchAll({ type: "window", includeUncontrolled: true })
+      .then((windows) => (windows.length > 0 ? windows[0].focus() : self.clients.openWindow("/")))
+  );
+});
@@ -0,0 +1,304 @@
+use crate::osm::{OsmId, OsmTag, OsmWay};
+use std::fmt;
+
+/// A difference between two sets of tags.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagChange {
+    Add(OsmTag),
+    Modify { k: String, old: String, new: String },
+    Delete(OsmTag),
+}
+
+impl TagChange {
+    pub fn key(&self) -> &str {
+        match self {
+            TagChange::Add(tag) | TagChange::Delete(tag) => &tag.k,
+            TagChange::Modify { k, .. } => k,
+        }
+    }
+
+    /// The value of the tag after the change, if it is still there.
+    fn new_value(&self) -> Option<&str> {
+        match self {
+            TagChange::Add(tag) => Some(&tag.v),
+            TagChange::Modify { new, .. } => Some(new),
+            TagChange::Delete(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TagChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagChange::Add(tag) => write!(f, "+ {} = {}", tag.k, tag.v),
+            TagChange::Modify { k, old, new } => write!(f, "~ {} = {} → {}", k, old, new),
+            TagChange::Delete(tag) => write!(f, "− {} = {}", tag.k, tag.v),
+        }
+    }
+}
+
+/// Editing the tags of a way already in OSM.
+#[derive(Debug)]
+pub struct WayEdit {
+    pub way_id: OsmId,
+    /// The tags the edit started from.
+    pub original: Vec<OsmTag>,
+    /// The edited tags, as text with one `key=value` per line.
+    pub tags: String,
+    pub comment: String,
+}
+
+impl WayEdit {
+    pub fn new(way: &OsmWay) -> WayEdit {
+        WayEdit {
+            way_id: way.id,
+            original: way.tags.clone(),
+            tags: tags_to_text(&way.tags),
+            comment: format!("Update tags of {}", way.label()),
+        }
+    }
+
+    pub fn changes(&self) -> Result<Vec<TagChange>, String> {
+        Ok(diff_tags(&self.original, &parse_tags(&self.tags)?))
+    }
+
+    /// Whether someone else has changed the tags since the edit started.
+    pub fn is_outdated(&self, theirs: &OsmWay) -> bool {
+        theirs.tags != self.original
+    }
+
+    /// The way with the edited tags. Only the tags are changed, so this should be the latest
+    /// version of the way from OSM, with all its nodes. The version of the way tells OSM which
+    /// version the edit is based on, so it can refuse edits of outdated data.
+    pub fn to_way(&self, way: &OsmWay) -> Result<OsmWay, String> {
+        let mut way = way.clone();
+        way.tags = parse_tags(&self.tags)?;
+        Ok(way)
+    }
+
+    /// Makes the same changes to a newer version of the way, where our values win over theirs.
+    pub fn rebase(&mut self, theirs: &OsmWay) -> Result<(), String> {
+        let changes = self.changes()?;
+        let mut tags = theirs.tags.clone();
+
+        for change in changes.iter() {
+            let index = tags.iter().position(|tag| tag.k == change.key());
+
+            match (change.new_value(), index) {
+                (Some(v), Some(i)) => tags[i].v = v.into(),
+                (Some(v), None) => tags.push(OsmTag {
+                    k: change.key().into(),
+                    v: v.into(),
+                }),
+                (None, Some(i)) => {
+                    tags.remove(i);
+                }
+                (None, None) => (),
+            }
+        }
+
+        self.original = theirs.tags.clone();
+        self.tags = tags_to_text(&tags);
+        Ok(())
+    }
+}
+
+/// The keys both sides changed, to different values.
+pub fn conflicting_keys<'a>(mine: &'a [TagChange], theirs: &[TagChange]) -> Vec<&'a str> {
+    mine.iter()
+        .filter(|change| {
+            theirs
+                .iter()
+                .any(|their| their.key() == change.key() && their.new_value() != change.new_value())
+        })
+        .map(TagChange::key)
+        .collect()
+}
+
+pub fn diff_tags(old: &[OsmTag], new: &[OsmTag]) -> Vec<TagChange> {
+    let mut changes = vec![];
+
+    for old_tag in old {
+        match new.iter().find(|tag| tag.k == old_tag.k) {
+            Some(new_tag) if new_tag.v != old_tag.v => changes.push(TagChange::Modify {
+                k: old_tag.k.clone(),
+                old: old_tag.v.clone(),
+                new: new_tag.v.clone(),
+            }),
+            Some(_) => (),
+            None => changes.push(TagChange::Delete(old_tag.clone())),
+        }
+    }
+
+    for new_tag in new {
+        if !old.iter().any(|tag| tag.k == new_tag.k) {
+            changes.push(TagChange::Add(new_tag.clone()));
+        }
+    }
+
+    changes
+}
+
+pub fn tags_to_text(tags: &[OsmTag]) -> String {
+    tags.iter()
+        .map(|tag| format!("{}={}\n", tag.k, tag.v))
+        .collect()
+}
+
+/// Reads tags written as one `key=value` per line. Keys left without a value are skipped, so
+/// clearing a value removes the tag.
+pub fn parse_tags(text: &str) -> Result<Vec<OsmTag>, String> {
+    let mut tags: Vec<OsmTag> = vec![];
+
+    for line in parse_lines(text) {
+        let (key, value) = line?;
+
+        if value.is_empty() {
+            continue;
+        }
+
+        if tags.iter().any(|tag| tag.k == key) {
+            return Err(format!("The key {} is used more than once", key));
+        }
+
+        tags.push(OsmTag { k: key, v: value });
+    }
+
+    Ok(tags)
+}
+
+pub fn parse_lines(text: &str) -> impl Iterator<Item = Result<(String, String), String>> + '_ {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('=') {
+            Some((key, _)) if key.trim().is_empty() => Err(format!("Missing key in {}", line)),
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("Expected key=value, got {}", line)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm::OsmNd;
+
+    fn tag(k: &str, v: &str) -> OsmTag {
+        OsmTag {
+            k: k.into(),
+            v: v.into(),
+        }
+    }
+
+    fn way(version: u32, tags: Vec<OsmTag>) -> OsmWay {
+        OsmWay {
+            id: 10,
+            version: Some(version),
+            nds: vec![],
+            tags,
+        }
+    }
+
+    #[test]
+    fn test_diff_tags() {
+        let old = [tag("highway", "residential"), tag("surface", "gravel")];
+        let new = [
+            tag("highway", "residential"),
+            tag("surface", "asphalt"),
+            tag("name", "Storgata"),
+        ];
+
+        assert_eq!(
+            diff_tags(&old, &new),
+            vec![
+                TagChange::Modify {
+                    k: "surface".into(),
+                    old: "gravel".into(),
+                    new: "asphalt".into()
+                },
+                TagChange::Add(tag("name", "Storgata"))
+            ]
+        );
+        assert_eq!(
+            diff_tags(&new, &old)[1],
+            TagChange::Delete(tag("name", "Storgata"))
+        );
+        assert_eq!(diff_tags(&old, &new)[1].to_string(), "+ name = Storgata");
+    }
+
+    #[test]
+    fn test_edit_way() {
+        let way = way(
+            3,
+            vec![tag("highway", "residential"), tag("surface", "gravel")],
+        );
+        let mut edit = WayEdit::new(&way);
+        assert_eq!(edit.tags, "highway=residential\nsurface=gravel\n");
+        assert_eq!(edit.changes(), Ok(vec![]));
+
+        edit.tags = "highway=residential\nsurface=\nname=Storgata\n".into();
+        assert_eq!(edit.changes().expect("Invalid tags").len(), 2);
+
+        // Their way may have moved on, with new nodes, but with the same tags
+        let mut theirs = way;
+        theirs.version = Some(4);
+        theirs.nds = vec![OsmNd { node_ref: 1 }, OsmNd { node_ref: 2 }];
+        assert!(!edit.is_outdated(&theirs));
+
+        let edited = edit.to_way(&theirs).expect("Invalid tags");
+        assert_eq!(edited.version, Some(4));
+        assert_eq!(edited.nds, theirs.nds);
+        assert_eq!(
+            edited.tags,
+            vec![tag("highway", "residential"), tag("name", "Storgata")]
+        );
+    }
+
+    #[test]
+    fn test_rebase_on_conflict() {
+        let base = way(
+            3,
+            vec![tag("highway", "residential"), tag("surface", "gravel")],
+        );
+        let mut edit = WayEdit::new(&base);
+        edit.tags = "highway=residential\nsurface=asphalt\nname=Storgata\n".into();
+
+        let theirs = way(
+            4,
+            vec![
+                tag("highway", "residential"),
+                tag("surface", "paved"),
+                tag("lit", "yes"),
+            ],
+        );
+        assert!(edit.is_outdated(&theirs));
+
+        let their_changes = diff_tags(&base.tags, &theirs.tags);
+        let my_changes = edit.changes().expect("Invalid tags");
+        assert_eq!(
+            conflicting_keys(&my_changes, &their_changes),
+            vec!["surface"]
+        );
+
+        edit.rebase(&theirs).expect("Invalid tags");
+        assert_eq!(
+            edit.tags,
+            "highway=residential\nsurface=asphalt\nlit=yes\nname=Storgata\n"
+        );
+        assert_eq!(edit.changes().expect("Invalid tags").len(), 2);
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(
+            parse_tags(" amenity = bench \n\nname=\n"),
+            Ok(vec![tag("amenity", "bench")])
+        );
+        assert_eq!(
+            parse_tags("amenity=bench\nbench"),
+            Err("Expected key=value, got bench".into())
+        );
+        assert!(parse_tags("amenity=bench\n=wood").is_err());
+        assert!(parse_tags("amenity=bench\namenity=waste_basket").is_err());
+    }
+}
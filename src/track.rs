@@ -0,0 +1,303 @@
+use crate::{bindings::GeolocationPosition, geo::Coord};
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct TrackPoint {
+    pub time: f64,
+    pub position: Coord,
+    pub accuracy: Option<f64>,
+    pub altitude: Option<f64>,
+    pub speed: Option<f64>,
+}
+
+/// The fixes recorded during a survey session. A new segment is started every time recording is
+/// resumed, so pauses don't show up as straight lines.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Track {
+    pub segments: Vec<Vec<TrackPoint>>,
+    pub recording: bool,
+    #[serde(default)]
+    pub trace_id: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceVisibility {
+    Private,
+    Trackable,
+    Identifiable,
+}
+
+impl TraceVisibility {
+    pub const ALL: [TraceVisibility; 3] = [
+        TraceVisibility::Private,
+        TraceVisibility::Trackable,
+        TraceVisibility::Identifiable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TraceVisibility::Private => "private",
+            TraceVisibility::Trackable => "trackable",
+            TraceVisibility::Identifiable => "identifiable",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TraceVisibility::Private => "Private (shown anonymously, unordered points)",
+            TraceVisibility::Trackable => "Trackable (shown anonymously, ordered points)",
+            TraceVisibility::Identifiable => "Identifiable (shown with your name)",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<TraceVisibility> {
+        TraceVisibility::ALL
+            .into_iter()
+            .find(|visibility| visibility.as_str() == value)
+    }
+}
+
+impl From<GeolocationPosition> for TrackPoint {
+    fn from(position: GeolocationPosition) -> Self {
+        let coords = position.coords();
+
+        TrackPoint {
+            time: position.timestamp(),
+            accuracy: Some(coords.accuracy()),
+            altitude: coords.altitude(),
+            speed: coords.speed(),
+            position: coords.into(),
+        }
+    }
+}
+
+impl Track {
+    pub fn start(&mut self) {
+        self.recording = true;
+        self.segments.push(vec![]);
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+        self.segments.retain(|segment| !segment.is_empty());
+    }
+
+    pub fn record(&mut self, point: TrackPoint) -> bool {
+        if !self.recording {
+            return false;
+        }
+
+        match self.segments.last_mut() {
+            Some(segment) if segment.last().map(|p| p.position) != Some(point.position) => {
+                segment.push(point);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.iter().all(|segment| segment.is_empty())
+    }
+
+    pub fn point_count(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len()).sum()
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.windows(2))
+            .map(|pair| pair[0].position.distance(&pair[1].position))
+            .sum()
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.segments
+            .iter()
+            .filter_map(|segment| Some(segment.last()?.time - segment.first()?.time))
+            .sum()
+    }
+
+    pub fn start_time(&self) -> Option<f64> {
+        self.segments
+            .iter()
+            .flatten()
+            .next()
+            .map(|point| point.time)
+    }
+
+    /// Serializes the track as a GPX 1.1 document. Accuracy and speed have no place in GPX 1.1, so
+    /// they are written as extensions.
+    pub fn to_gpx(&self, name: &str) -> String {
+        let mut gpx = String::new();
+
+        gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        gpx.push_str(
+            "<gpx version=\"1.1\" creator=\"Surway\" \
+            xmlns=\"http://www.topografix.com/GPX/1/1\" \
+            xmlns:surway=\"https://surway.hovland.xyz/gpx/1\">\n",
+        );
+
+        if let Some(time) = self.start_time() {
+            writeln!(
+                gpx,
+                "  <metadata><time>{}</time></metadata>",
+                iso_time(time)
+            )
+            .unwrap();
+        }
+
+        gpx.push_str("  <trk>\n");
+        writeln!(gpx, "    <name>{}</name>", xml_escape(name)).unwrap();
+
+        for segment in self.segments.iter().filter(|segment| !segment.is_empty()) {
+            gpx.push_str("    <trkseg>\n");
+
+            for point in segment {
+                write!(
+                    gpx,
+                    "      <trkpt lat=\"{}\" lon=\"{}\">",
+                    point.position.lat, point.position.lon
+                )
+                .unwrap();
+
+                if let Some(altitude) = point.altitude {
+                    write!(gpx, "<ele>{}</ele>", altitude).unwrap();
+                }
+
+                write!(gpx, "<time>{}</time>", iso_time(point.time)).unwrap();
+
+                if point.accuracy.is_some() || point.speed.is_some() {
+                    gpx.push_str("<extensions>");
+
+                    if let Some(accuracy) = point.accuracy {
+                        write!(gpx, "<surway:accuracy>{}</surway:accuracy>", accuracy).unwrap();
+                    }
+
+                    if let Some(speed) = point.speed {
+                        write!(gpx, "<surway:speed>{}</surway:speed>", speed).unwrap();
+                    }
+
+                    gpx.push_str("</extensions>");
+                }
+
+                gpx.push_str("</trkpt>\n");
+            }
+
+            gpx.push_str("    </trkseg>\n");
+        }
+
+        gpx.push_str("  </trk>\n");
+        gpx.push_str("</gpx>\n");
+        gpx
+    }
+}
+
+pub fn xml_escape(text: &str) -> String {
+    String::from_utf8_lossy(&escape(text.as_bytes())).into_owned()
+}
+
+/// Formats milliseconds since the Unix epoch as an ISO 8601 UTC timestamp.
+pub fn iso_time(time: f64) -> String {
+    let seconds = (time / 1000.0).floor() as i64;
+    let (days, seconds_of_day) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Civil from days, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn point(time: f64, lat: f64, lon: f64) -> TrackPoint {
+        TrackPoint {
+            time,
+            position: Coord { lat, lon },
+            accuracy: Some(5.0),
+            altitude: None,
+            speed: None,
+        }
+    }
+
+    #[test]
+    fn test_iso_time() {
+        assert_eq!(iso_time(0.0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_time(1640995200000.0), "2022-01-01T00:00:00Z");
+        assert_eq!(iso_time(1709210096789.0), "2024-02-29T12:34:56Z");
+        assert_eq!(iso_time(-1000.0), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_recording_segments() {
+        let mut track = Track::default();
+
+        assert!(!track.record(point(0.0, 60.0, 5.0)));
+
+        track.start();
+        assert!(track.record(point(1000.0, 60.0, 5.0)));
+        assert!(!track.record(point(2000.0, 60.0, 5.0)));
+        assert!(track.record(point(3000.0, 60.001, 5.0)));
+        track.stop();
+
+        track.start();
+        track.stop();
+
+        track.start();
+        track.record(point(10000.0, 60.002, 5.0));
+        track.record(point(15000.0, 60.003, 5.0));
+
+        assert_eq!(track.segments.len(), 2);
+        assert_eq!(track.point_count(), 4);
+        assert_approx_eq!(track.distance(), 222.4, 0.1);
+        assert_approx_eq!(track.duration(), 7000.0);
+    }
+
+    #[test]
+    fn test_to_gpx() {
+        let mut track = Track::default();
+        track.start();
+        track.record(TrackPoint {
+            altitude: Some(12.5),
+            speed: Some(1.2),
+            ..point(1640995200000.0, 60.39, 5.32)
+        });
+        track.record(point(1640995201000.0, 60.391, 5.321));
+
+        let gpx = track.to_gpx("Bergen & back");
+
+        assert!(gpx.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\""));
+        assert!(gpx.contains("<name>Bergen &amp; back</name>"));
+        assert!(gpx.contains("<metadata><time>2022-01-01T00:00:00Z</time></metadata>"));
+        assert!(gpx.contains(
+            "<trkpt lat=\"60.39\" lon=\"5.32\"><ele>12.5</ele><time>2022-01-01T00:00:00Z</time>\
+            <extensions><surway:accuracy>5</surway:accuracy><surway:speed>1.2</surway:speed>\
+            </extensions></trkpt>"
+        ));
+        assert_eq!(gpx.matches("<trkseg>").count(), 1);
+        assert_eq!(gpx.matches("<trkpt ").count(), 2);
+        assert!(gpx.ends_with("</trk>\n</gpx>\n"));
+    }
+}
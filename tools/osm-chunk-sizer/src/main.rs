@@ -10,9 +10,37 @@ use reqwest::StatusCode;
 use stopwatch::Stopwatch;
 
 mod geo;
+#[path = "../../../src/pbf.rs"]
+mod pbf;
+
+const DEFAULT_OVERPASS_ENDPOINT: &str = "https://overpass-api.de/api/interpreter";
+
+/// Where to get the chunks from. Set `OSM_PBF` to the path of an extract to measure chunk sizes
+/// offline, instead of downloading from Overpass.
+enum Source {
+    Overpass(String),
+    Pbf(Vec<u8>),
+}
+
+impl Source {
+    fn from_env() -> Source {
+        match std::env::var("OSM_PBF") {
+            Ok(path) => Source::Pbf(std::fs::read(path).expect("Could not read PBF file")),
+            Err(_) => Source::Overpass(overpass_endpoint()),
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Source::Overpass(_) => "downloaded data (MB)",
+            Source::Pbf(_) => "read elements",
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
+    let source = Source::from_env();
     let file = File::open("gpx/wow.gpx").expect("Could not open GPX file");
     let reader = BufReader::new(file);
     let gpx: Gpx = read(reader).unwrap();
@@ -22,9 +50,9 @@ async fn main() {
     let mut results: Vec<ChunkSizeResult> = vec![];
 
     loop {
-        report(&results);
+        report(&results, &source);
 
-        results.push(test_chunk_size(segment, chunk_size).await);
+        results.push(test_chunk_size(segment, chunk_size, &source).await);
         let last_result = results.last().unwrap();
 
         if chunk_size < 75.0 {
@@ -48,10 +76,10 @@ async fn main() {
         chunk_size *= 0.7;
     }
 
-    report(&results);
+    report(&results, &source);
 }
 
-fn report(results: &Vec<ChunkSizeResult>) {
+fn report(results: &Vec<ChunkSizeResult>, source: &Source) {
     for result in results {
         println!();
         println!("Chunk size: {} m", result.chunk_size);
@@ -60,10 +88,11 @@ fn report(results: &Vec<ChunkSizeResult>) {
             println!("Failed: {}", result.error_message);
         } else {
             println!("Request count: {}", result.request_count);
-            println!(
-                "Total downloaded data: {} MB",
-                (result.total_downloaded_data_size as f64) / 1024.0 / 1024.0
-            );
+            let size = match source {
+                Source::Overpass(_) => (result.total_downloaded_data_size as f64) / 1024.0 / 1024.0,
+                Source::Pbf(_) => result.total_downloaded_data_size as f64,
+            };
+            println!("Total {}: {}", source.unit(), size);
             println!(
                 "Total request time: {} min",
                 (result.total_request_time as f64) / 1000.0 / 60.0
@@ -89,7 +118,11 @@ struct BoundingBox {
     upper_right: Point<f64>,
 }
 
-async fn test_chunk_size(segment: &TrackSegment, chunk_size: f64) -> ChunkSizeResult {
+async fn test_chunk_size(
+    segment: &TrackSegment,
+    chunk_size: f64,
+    source: &Source,
+) -> ChunkSizeResult {
     println!("Testing chunk size {} m", chunk_size);
 
     let mut bbox: BoundingBox = Default::default();
@@ -127,15 +160,22 @@ async fn test_chunk_size(segment: &TrackSegment, chunk_size: f64) -> ChunkSizeRe
             let sw = Stopwatch::start_new();
             request_count += 1;
 
-            match send_osm_request(&bbox).await {
+            let result = match source {
+                Source::Overpass(endpoint) => {
+                    send_osm_request(endpoint, &bbox).await.map(|osm| osm.len())
+                }
+                Source::Pbf(data) => read_pbf_chunk(data, &bbox),
+            };
+
+            match result {
                 Err(s) => {
                     failed = true;
                     error_message = s;
                     total_downloaded_data_size = usize::MAX;
                     break;
                 }
-                Ok(osm) => {
-                    total_downloaded_data_size += osm.len();
+                Ok(size) => {
+                    total_downloaded_data_size += size;
                     total_request_time += sw.elapsed_ms();
                 }
             };
@@ -203,8 +243,36 @@ fn get_osm_query(bbox: &BoundingBox) -> String {
 //     )
 // }
 
-async fn send_osm_request(bbox: &BoundingBox) -> Result<String, String> {
-    let url = "https://overpass-api.de/api/interpreter";
+/// The Overpass server to use, which can be set with the `OVERPASS_ENDPOINT` environment variable.
+fn overpass_endpoint() -> String {
+    std::env::var("OVERPASS_ENDPOINT").unwrap_or_else(|_| DEFAULT_OVERPASS_ENDPOINT.into())
+}
+
+/// Counts the highways in the chunk, along with their nodes.
+fn read_pbf_chunk(data: &[u8], bbox: &BoundingBox) -> Result<usize, String> {
+    let bounds = pbf::Bounds {
+        min_lat: bbox.lower_left.lat(),
+        min_lon: bbox.lower_left.lng(),
+        max_lat: bbox.upper_right.lat(),
+        max_lon: bbox.upper_right.lng(),
+    };
+
+    let chunk = pbf::read(data, &bounds)?;
+    let highways: Vec<&pbf::PbfWay> = chunk
+        .ways
+        .iter()
+        .filter(|way| way.tags.iter().any(|(key, _)| key == "highway"))
+        .collect();
+
+    let nodes: std::collections::HashSet<i64> = highways
+        .iter()
+        .flat_map(|way| way.refs.iter().copied())
+        .collect();
+
+    Ok(highways.len() + nodes.len())
+}
+
+async fn send_osm_request(url: &str, bbox: &BoundingBox) -> Result<String, String> {
     let query = get_osm_query(bbox);
     println!("Fetching query {}", query);
 